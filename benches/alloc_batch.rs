@@ -0,0 +1,28 @@
+//! Cold-start burst: a burst of enqueues on a fresh queue, whose empty node cache makes every
+//! enqueue miss, with allocation batches of 1, 8 and 32 nodes.
+//!
+//! Run with `cargo bench --bench alloc_batch`.
+
+use std::time::{Duration, Instant};
+
+use umpmc::Queue;
+
+const BURST: usize = 10_000;
+const RUNS: usize = 100;
+
+fn run(batch: usize) -> Duration {
+    let queue = Queue::with_alloc_batch(batch);
+    let start = Instant::now();
+    (0..BURST).for_each(|i| queue.enqueue(i));
+    start.elapsed()
+}
+
+fn main() {
+    for batch in [1, 8, 32] {
+        let mut runs: Vec<_> = (0..RUNS).map(|_| run(batch)).collect();
+        runs.sort_unstable();
+        let median = runs[RUNS / 2];
+        let per_item = median.as_nanos() as f64 / BURST as f64;
+        println!("batch {batch:<4} {median:>12.3?} {per_item:>8.2} ns/item");
+    }
+}
@@ -0,0 +1,76 @@
+//! Consumer polling `dequeue` against a bursty and a sparse producer, with the default spin
+//! count and with `Queue::with_auto_spin`.
+//!
+//! Reports the consumer time per received item and the number of dequeues which returned no
+//! data; run with `cargo bench --bench auto_spin`.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use umpmc::{Dequeue, Queue};
+
+const BURSTS: usize = 200;
+const BURST_LEN: usize = 1_000;
+const SPARSE_ITEMS: usize = 2_000;
+
+fn bursty(queue: &Queue<usize>) {
+    for _ in 0..BURSTS {
+        (0..BURST_LEN).for_each(|i| queue.enqueue(i));
+        std::thread::sleep(Duration::from_micros(500));
+    }
+}
+
+fn sparse(queue: &Queue<usize>) {
+    for i in 0..SPARSE_ITEMS {
+        queue.enqueue(i);
+        std::thread::sleep(Duration::from_micros(20));
+    }
+}
+
+fn run(queue: Queue<usize>, items: usize, producer: fn(&Queue<usize>)) -> (Duration, usize) {
+    let queue = Arc::new(queue);
+    let producer = {
+        let queue = queue.clone();
+        std::thread::spawn(move || producer(&queue))
+    };
+    let (mut received, mut missed) = (0, 0);
+    let mut busy = Duration::ZERO;
+    while received < items {
+        let start = Instant::now();
+        let res = queue.dequeue();
+        busy += start.elapsed();
+        match res {
+            Dequeue::Data(_) => received += 1,
+            _ => {
+                missed += 1;
+                std::thread::yield_now();
+            }
+        }
+    }
+    producer.join().unwrap();
+    (busy, missed)
+}
+
+fn report(name: &str, new: fn() -> Queue<usize>, items: usize, producer: fn(&Queue<usize>)) {
+    let (busy, missed) = run(new(), items, producer);
+    let per_item = busy.as_nanos() as f64 / items as f64;
+    println!("{name:<20} {per_item:>10.2} ns/item {missed:>10} missed");
+}
+
+fn main() {
+    let bursty_items = BURSTS * BURST_LEN;
+    report("bursty new", Queue::new, bursty_items, bursty);
+    report(
+        "bursty auto spin",
+        Queue::with_auto_spin,
+        bursty_items,
+        bursty,
+    );
+    report("sparse new", Queue::new, SPARSE_ITEMS, sparse);
+    report(
+        "sparse auto spin",
+        Queue::with_auto_spin,
+        SPARSE_ITEMS,
+        sparse,
+    );
+}
@@ -0,0 +1,51 @@
+//! Node recycling throughput with many threads each enqueuing and dequeuing in turn, so that
+//! every operation pops a node from the cache or puts one back, with the single-stack cache
+//! and with `Queue::with_cache_shards`.
+//!
+//! Run with `cargo bench --bench cache_shards`; the difference only shows with several cores.
+
+use std::sync::{Arc, Barrier};
+use std::time::{Duration, Instant};
+
+use umpmc::Queue;
+
+const THREADS: usize = 8;
+const ROUNDS: usize = 200_000;
+const RUNS: usize = 3;
+
+fn run(new: impl Fn() -> Queue<usize>) -> Duration {
+    let queue = Arc::new(new());
+    let barrier = Arc::new(Barrier::new(THREADS + 1));
+    let threads: Vec<_> = (0..THREADS)
+        .map(|_| {
+            let (queue, barrier) = (queue.clone(), barrier.clone());
+            std::thread::spawn(move || {
+                barrier.wait();
+                for i in 0..ROUNDS {
+                    queue.enqueue(i);
+                    queue.dequeue();
+                }
+            })
+        })
+        .collect();
+    barrier.wait();
+    let start = Instant::now();
+    threads.into_iter().for_each(|t| t.join().unwrap());
+    start.elapsed()
+}
+
+fn report(name: &str, new: impl Fn() -> Queue<usize>) {
+    let best = (0..RUNS).map(|_| run(&new)).min().unwrap();
+    let ops = (THREADS * ROUNDS) as f64;
+    let per_op = best.as_nanos() as f64 / ops;
+    println!("{name:<12} {best:>12.3?} {per_op:>8.2} ns/round-trip");
+}
+
+fn main() {
+    report("1 shard", Queue::new);
+    for shards in [2, 4, 8] {
+        report(&format!("{shards} shards"), || {
+            Queue::with_cache_shards(shards)
+        });
+    }
+}
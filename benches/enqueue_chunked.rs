@@ -0,0 +1,74 @@
+//! Latency of single enqueues competing with bulk producers, which publish whole batches with
+//! `enqueue_atomic` or chunked ones with `enqueue_iter_chunked`.
+//!
+//! Reports the latency percentiles of the competing producer; run with
+//! `cargo bench --bench enqueue_chunked`.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Barrier};
+use std::time::{Duration, Instant};
+
+use umpmc::Queue;
+
+const BULK_PRODUCERS: usize = 2;
+const BATCHES: usize = 20;
+const BATCH_LEN: usize = 50_000;
+const SINGLE_ENQUEUES: usize = 100_000;
+
+fn run(bulk: fn(&Queue<usize>)) -> Vec<Duration> {
+    let queue = Arc::new(Queue::new());
+    let done = Arc::new(AtomicBool::new(false));
+    let barrier = Arc::new(Barrier::new(BULK_PRODUCERS + 1));
+    let bulk_producers: Vec<_> = (0..BULK_PRODUCERS)
+        .map(|_| {
+            let (queue, barrier) = (queue.clone(), barrier.clone());
+            std::thread::spawn(move || {
+                barrier.wait();
+                (0..BATCHES).for_each(|_| bulk(&queue));
+            })
+        })
+        .collect();
+    let consumer = {
+        let (queue, done) = (queue.clone(), done.clone());
+        std::thread::spawn(move || {
+            while !done.load(Ordering::Relaxed) {
+                queue.dequeue();
+            }
+        })
+    };
+    barrier.wait();
+    let mut latencies: Vec<_> = (0..SINGLE_ENQUEUES)
+        .map(|i| {
+            let start = Instant::now();
+            queue.enqueue(i);
+            start.elapsed()
+        })
+        .collect();
+    bulk_producers.into_iter().for_each(|t| t.join().unwrap());
+    done.store(true, Ordering::Relaxed);
+    consumer.join().unwrap();
+    latencies.sort_unstable();
+    latencies
+}
+
+fn report(name: &str, bulk: fn(&Queue<usize>)) {
+    let latencies = run(bulk);
+    let percentile = |p: usize| latencies[(latencies.len() - 1) * p / 100];
+    let (p50, p99, max) = (percentile(50), percentile(99), percentile(100));
+    println!("{name:<10} p50 {p50:>10.3?} p99 {p99:>10.3?} max {max:>10.3?}");
+}
+
+fn atomic(queue: &Queue<usize>) {
+    queue.enqueue_atomic(0..BATCH_LEN);
+}
+
+fn chunked<const CHUNK: usize>(queue: &Queue<usize>) {
+    queue.enqueue_iter_chunked(0..BATCH_LEN, CHUNK);
+}
+
+fn main() {
+    report("atomic", atomic);
+    report("chunk 16", chunked::<16>);
+    report("chunk 256", chunked::<256>);
+    report("chunk 4096", chunked::<4096>);
+}
@@ -0,0 +1,100 @@
+//! Handoff latency from a producer to consumers parked on every CPU, with FIFO wake ordering
+//! and with `SynchronizedQueue::with_local_wake`, split between the consumers on the NUMA node
+//! of the producer and the ones on another node.
+//!
+//! Run with `cargo bench --bench local_wake`; it requires Linux with glibc, and the modes only
+//! differ with several NUMA nodes.
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+mod numa {
+    use std::os::raw::{c_int, c_uint};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use umpmc::SynchronizedQueue;
+
+    const HANDOFFS: usize = 2_000;
+    const NODES: usize = 8;
+
+    extern "C" {
+        fn getcpu(cpu: *mut c_uint, node: *mut c_uint) -> c_int;
+        fn sched_setaffinity(pid: c_int, size: usize, mask: *const u64) -> c_int;
+    }
+
+    fn pin(cpu: usize) {
+        let mut mask = [0u64; 16];
+        mask[cpu / 64] |= 1 << (cpu % 64);
+        let res = unsafe { sched_setaffinity(0, std::mem::size_of_val(&mask), mask.as_ptr()) };
+        assert_eq!(res, 0, "cannot pin thread to CPU {cpu}");
+    }
+
+    fn node() -> usize {
+        let (mut cpu, mut node) = (0, 0);
+        assert_eq!(unsafe { getcpu(&mut cpu, &mut node) }, 0);
+        node as usize
+    }
+
+    /// Return the latencies of the handoffs to the local consumers and to the remote ones.
+    fn run(queue: SynchronizedQueue<Option<Instant>>) -> [Vec<Duration>; 2] {
+        let cpus = std::thread::available_parallelism().unwrap().get();
+        pin(0);
+        let producer_node = node();
+        let queue = Arc::new(queue);
+        let received = Arc::new(AtomicUsize::new(0));
+        let latencies = Arc::new(Mutex::new([Vec::new(), Vec::new()]));
+        let consumers: Vec<_> = (1..cpus.max(2))
+            .map(|cpu| {
+                let (queue, received) = (queue.clone(), received.clone());
+                let latencies = latencies.clone();
+                std::thread::spawn(move || {
+                    pin(cpu % cpus);
+                    let remote = node() != producer_node;
+                    while let Some(sent) = queue.dequeue() {
+                        let latency = sent.elapsed();
+                        latencies.lock().unwrap()[remote as usize].push(latency);
+                        received.fetch_add(1, Ordering::Release);
+                    }
+                })
+            })
+            .collect();
+        for i in 0..HANDOFFS {
+            // let the consumers park again
+            std::thread::sleep(Duration::from_micros(200));
+            queue.enqueue(Some(Instant::now()));
+            while received.load(Ordering::Acquire) <= i {
+                std::thread::yield_now();
+            }
+        }
+        consumers.iter().for_each(|_| queue.enqueue(None));
+        consumers.into_iter().for_each(|t| t.join().unwrap());
+        let mut latencies = std::mem::take(&mut *latencies.lock().unwrap());
+        latencies.iter_mut().for_each(|l| l.sort_unstable());
+        latencies
+    }
+
+    fn report(name: &str, queue: SynchronizedQueue<Option<Instant>>) {
+        let [local, remote] = run(queue);
+        let median = |l: &[Duration]| l.get(l.len() / 2).copied().unwrap_or_default();
+        let (local_median, remote_median) = (median(&local), median(&remote));
+        let (local, remote) = (local.len(), remote.len());
+        println!(
+            "{name:<12} local {local:>6} p50 {local_median:>10.3?} remote {remote:>6} p50 {remote_median:>10.3?}"
+        );
+    }
+
+    pub fn main() {
+        report("fifo", SynchronizedQueue::new());
+        report("local wake", SynchronizedQueue::with_local_wake(NODES));
+    }
+}
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+fn main() {
+    numa::main();
+}
+
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+fn main() {
+    eprintln!("the local_wake bench requires Linux with glibc");
+}
@@ -0,0 +1,53 @@
+//! Single producer publishing to one consumer, through the general `enqueue` and through the
+//! `Producer` handle of `into_single_producer`.
+//!
+//! Run with `cargo bench --bench single_producer`.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use umpmc::{Dequeue, Queue};
+
+const ITEMS: usize = 1_000_000;
+const RUNS: usize = 5;
+
+fn drain(mut dequeue: impl FnMut() -> Dequeue<usize>) {
+    let mut received = 0;
+    while received < ITEMS {
+        if let Dequeue::Data(_) = dequeue() {
+            received += 1;
+        }
+    }
+}
+
+fn general() -> Duration {
+    let queue = Arc::new(Queue::new());
+    let start = Instant::now();
+    let consumer = {
+        let queue = queue.clone();
+        std::thread::spawn(move || drain(|| queue.dequeue()))
+    };
+    (0..ITEMS).for_each(|i| queue.enqueue(i));
+    consumer.join().unwrap();
+    start.elapsed()
+}
+
+fn single_producer() -> Duration {
+    let (mut producer, consumer) = Queue::new().into_single_producer();
+    let start = Instant::now();
+    let consumer = std::thread::spawn(move || drain(|| consumer.dequeue()));
+    (0..ITEMS).for_each(|i| producer.enqueue(i));
+    consumer.join().unwrap();
+    start.elapsed()
+}
+
+fn report(name: &str, run: fn() -> Duration) {
+    let best = (0..RUNS).map(|_| run()).min().unwrap();
+    let per_item = best.as_nanos() as f64 / ITEMS as f64;
+    println!("{name:<16} {best:>12.3?} {per_item:>8.2} ns/item");
+}
+
+fn main() {
+    report("enqueue", general);
+    report("Producer", single_producer);
+}
@@ -0,0 +1,60 @@
+//! Producers and consumers sharing a queue, with consumers spinning on `dequeue_spin` using
+//! each [`SpinStrategy`].
+//!
+//! Run with `cargo bench --bench spin_strategy`; the strategies mostly differ on SMT or hybrid
+//! CPUs, where a spinning consumer competes with a producer for the same core.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Barrier};
+use std::time::{Duration, Instant};
+
+use umpmc::{Dequeue, Queue, SpinStrategy};
+
+const PRODUCERS: usize = 2;
+const CONSUMERS: usize = 2;
+const ITEMS: usize = 500_000;
+const SPIN: usize = 256;
+const RUNS: usize = 3;
+
+fn run(strategy: SpinStrategy) -> Duration {
+    let queue = Arc::new(Queue::with_spin_strategy(strategy));
+    let received = Arc::new(AtomicUsize::new(0));
+    let barrier = Arc::new(Barrier::new(PRODUCERS + CONSUMERS + 1));
+    let producers = (0..PRODUCERS).map(|_| {
+        let (queue, barrier) = (queue.clone(), barrier.clone());
+        std::thread::spawn(move || {
+            barrier.wait();
+            (0..ITEMS / PRODUCERS).for_each(|i| queue.enqueue(i));
+        })
+    });
+    let consumers = (0..CONSUMERS).map(|_| {
+        let (queue, barrier, received) = (queue.clone(), barrier.clone(), received.clone());
+        std::thread::spawn(move || {
+            barrier.wait();
+            while received.load(Ordering::Relaxed) < ITEMS {
+                if let Dequeue::Data(_) = queue.dequeue_spin(SPIN) {
+                    received.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        })
+    });
+    let threads: Vec<_> = producers.chain(consumers).collect();
+    barrier.wait();
+    let start = Instant::now();
+    threads.into_iter().for_each(|t| t.join().unwrap());
+    start.elapsed()
+}
+
+fn main() {
+    for strategy in [
+        SpinStrategy::Pause,
+        SpinStrategy::Yield,
+        SpinStrategy::PauseThenYield,
+        SpinStrategy::Noop,
+    ] {
+        let best = (0..RUNS).map(|_| run(strategy)).min().unwrap();
+        let per_item = best.as_nanos() as f64 / ITEMS as f64;
+        let name = format!("{strategy:?}");
+        println!("{name:<16} {best:>12.3?} {per_item:>8.2} ns/item");
+    }
+}
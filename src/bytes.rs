@@ -0,0 +1,137 @@
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{Dequeue, Queue};
+
+/// Queue of byte buffers keeping track of the total length of buffered payloads.
+///
+/// The length is added before the buffer is enqueued and subtracted after it is dequeued, so
+/// [`total_bytes`](ByteQueue::total_bytes) never underestimates the buffered payload.
+pub struct ByteQueue<T: AsRef<[u8]>> {
+    inner: Queue<T>,
+    bytes: AtomicUsize,
+}
+
+impl<T: AsRef<[u8]>> ByteQueue<T> {
+    pub fn new() -> Self {
+        ByteQueue {
+            inner: Queue::new(),
+            bytes: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn total_bytes(&self) -> usize {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn enqueue(&self, value: T) {
+        self.bytes
+            .fetch_add(value.as_ref().len(), Ordering::Relaxed);
+        self.inner.enqueue(value)
+    }
+
+    pub fn dequeue_spin(&self, spin: usize) -> Dequeue<T> {
+        let res = self.inner.dequeue_spin(spin);
+        if let Dequeue::Data(value) = &res {
+            self.bytes
+                .fetch_sub(value.as_ref().len(), Ordering::Relaxed);
+        }
+        res
+    }
+
+    pub fn dequeue(&self) -> Dequeue<T> {
+        self.dequeue_spin(0)
+    }
+
+    /// Dequeue buffers until at least `n` bytes are collected, or no buffer is available.
+    pub fn dequeue_until_bytes(&self, n: usize) -> Vec<T> {
+        let mut buffers = Vec::new();
+        let mut bytes = 0;
+        while bytes < n {
+            match self.dequeue() {
+                Dequeue::Data(value) => {
+                    bytes += value.as_ref().len();
+                    buffers.push(value);
+                }
+                _ => break,
+            }
+        }
+        buffers
+    }
+}
+
+impl<T: AsRef<[u8]>> Default for ByteQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: AsRef<[u8]>> Queue<T> {
+    /// Dequeue buffers and write them to `writer`, each prefixed by its length as a little-endian
+    /// `u64`, until no buffer is available; return the number of buffers written.
+    ///
+    /// On write error, draining stops and the error is returned together with the number of
+    /// buffers dequeued, including the failed one: it is dropped, not re-enqueued, as it may have
+    /// been partially written and re-enqueuing it would break the queue order anyway.
+    pub fn drain_to_writer<W: Write>(&self, writer: &mut W) -> Result<usize, (usize, io::Error)> {
+        let mut count = 0;
+        while let Dequeue::Data(value) = self.dequeue() {
+            count += 1;
+            let buffer = value.as_ref();
+            writer
+                .write_all(&(buffer.len() as u64).to_le_bytes())
+                .and_then(|_| writer.write_all(buffer))
+                .map_err(|err| (count, err))?;
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Write};
+
+    use crate::bytes::ByteQueue;
+    use crate::Queue;
+
+    #[test]
+    fn coalesce() {
+        let queue = ByteQueue::new();
+        for buffer in [&b"ab"[..], b"cde", b"f", b"ghij"] {
+            queue.enqueue(buffer.to_vec());
+        }
+        assert_eq!(queue.total_bytes(), 10);
+        assert_eq!(queue.dequeue_until_bytes(4).concat(), b"abcde");
+        assert_eq!(queue.total_bytes(), 5);
+        assert_eq!(queue.dequeue_until_bytes(100).concat(), b"fghij");
+        assert_eq!(queue.total_bytes(), 0);
+    }
+
+    #[test]
+    fn drain_to_writer() {
+        let queue = Queue::new();
+        queue.enqueue(b"ab".to_vec());
+        queue.enqueue(b"cde".to_vec());
+        let mut output = Vec::new();
+        assert_eq!(queue.drain_to_writer(&mut output).unwrap(), 2);
+        assert_eq!(output, b"\x02\0\0\0\0\0\0\0ab\x03\0\0\0\0\0\0\0cde");
+
+        struct Limited(usize);
+        impl Write for Limited {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                let n = buf.len().min(self.0);
+                self.0 -= n;
+                Ok(n)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+        for buffer in ["ab", "cde", "f"] {
+            queue.enqueue(buffer.as_bytes().to_vec());
+        }
+        let (count, err) = queue.drain_to_writer(&mut Limited(12)).unwrap_err();
+        assert_eq!((count, err.kind()), (2, io::ErrorKind::WriteZero));
+        assert_eq!(queue.dequeue().data(), Some(b"f".to_vec()));
+    }
+}
@@ -0,0 +1,188 @@
+use std::future::{poll_fn, Future};
+use std::mem::MaybeUninit;
+use std::sync::atomic::{self, AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::Poll;
+
+use crate::synchronized::Waker;
+use crate::{Dequeue, SynchronizedQueue};
+
+/// Endpoint of a bidirectional channel, see [`duplex`].
+pub struct Endpoint<S, R> {
+    send: Arc<SynchronizedQueue<S>>,
+    recv: Arc<SynchronizedQueue<R>>,
+    closed: Arc<AtomicBool>,
+}
+
+/// Create a bidirectional channel made of two [`SynchronizedQueue`]s, each endpoint sending
+/// to the queue the other receives from.
+///
+/// Dropping an endpoint disconnects both directions: the peer's `send` fails, and its `recv`
+/// returns `None` once the items already sent are received. An item sent concurrently with the
+/// drop may be accepted and never received.
+pub fn duplex<A, B>() -> (Endpoint<A, B>, Endpoint<B, A>) {
+    let (a_to_b, b_to_a) = (
+        Arc::new(SynchronizedQueue::new()),
+        Arc::new(SynchronizedQueue::new()),
+    );
+    let closed = Arc::new(AtomicBool::new(false));
+    (
+        Endpoint {
+            send: a_to_b.clone(),
+            recv: b_to_a.clone(),
+            closed: closed.clone(),
+        },
+        Endpoint {
+            send: b_to_a,
+            recv: a_to_b,
+            closed,
+        },
+    )
+}
+
+impl<S, R> Endpoint<S, R> {
+    /// Send an item to the peer, or return it if the peer is dropped.
+    pub fn send(&self, value: S) -> Result<(), S> {
+        if self.closed.load(Ordering::Acquire) {
+            return Err(value);
+        }
+        self.send.enqueue(value);
+        Ok(())
+    }
+
+    fn try_recv_or_register(&self, waker: impl FnOnce() -> Waker) -> Option<Option<R>> {
+        if let Dequeue::Data(value) = self.recv.try_dequeue() {
+            return Some(Some(value));
+        }
+        if self.closed.load(Ordering::Acquire) {
+            return Some(self.recv.try_dequeue().data());
+        }
+        let waker = Arc::new(waker());
+        self.recv.register_waker(waker.clone());
+        // pairs with the fence of `drop`
+        atomic::fence(Ordering::SeqCst);
+        if let Dequeue::Data(value) = self.recv.try_dequeue() {
+            waker.abort_unparked();
+            return Some(Some(value));
+        }
+        if self.closed.load(Ordering::Acquire) {
+            waker.abort_unparked();
+            // the flush of `drop` stops at a waker being registered, e.g. this one, so the
+            // waiters registered behind it may not have been woken
+            self.recv.flush_wakers();
+            return Some(self.recv.try_dequeue().data());
+        }
+        None
+    }
+
+    /// Receive an item from the peer, parking the thread until one is available, or return
+    /// `None` if the peer is dropped and all its items are received.
+    pub fn recv(&self) -> Option<R> {
+        loop {
+            if let Some(res) = self.try_recv_or_register(Waker::new_sync) {
+                return res;
+            }
+            std::thread::park();
+        }
+    }
+
+    /// Receive up to `N` items already sent by the peer without blocking nor allocating,
+    /// returning them in an array along with their count; only the first `count` slots are
+    /// initialized, and the caller must read or drop them, or the items are leaked.
+    pub fn try_recv_batch<const N: usize>(&self) -> ([MaybeUninit<R>; N], usize) {
+        let mut batch = [const { MaybeUninit::uninit() }; N];
+        let mut count = 0;
+        for slot in &mut batch {
+            let Dequeue::Data(value) = self.recv.try_dequeue() else {
+                break;
+            };
+            slot.write(value);
+            count += 1;
+        }
+        (batch, count)
+    }
+
+    pub fn recv_async(&self) -> impl Future<Output = Option<R>> + '_ {
+        poll_fn(
+            |cx| match self.try_recv_or_register(|| Waker::new_async(cx.waker().clone())) {
+                Some(res) => Poll::Ready(res),
+                None => Poll::Pending,
+            },
+        )
+    }
+}
+
+impl<S, R> Drop for Endpoint<S, R> {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::Release);
+        // pairs with the fence of `try_recv_or_register`
+        atomic::fence(Ordering::SeqCst);
+        self.send.flush_wakers();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::duplex;
+
+    #[test]
+    fn round_trip() {
+        let (client, server) = duplex::<u32, String>();
+        let server = std::thread::spawn(move || {
+            while let Some(request) = server.recv() {
+                server.send(format!("response {request}")).unwrap();
+            }
+            assert_eq!(server.send("late".into()), Err("late".into()));
+        });
+        client.send(0).unwrap();
+        assert_eq!(client.recv().as_deref(), Some("response 0"));
+        client.send(1).unwrap();
+        let response = futures::executor::block_on(client.recv_async());
+        assert_eq!(response.as_deref(), Some("response 1"));
+        drop(client);
+        server.join().unwrap();
+
+        let (client, server) = duplex::<u32, u32>();
+        client.send(42).unwrap();
+        drop(client);
+        assert_eq!(server.send(0), Err(0));
+        assert_eq!(server.recv(), Some(42));
+        assert_eq!(server.recv(), None);
+    }
+
+    #[test]
+    fn try_recv_batch() {
+        let (client, server) = duplex::<String, ()>();
+        (0..6).for_each(|i| client.send(i.to_string()).unwrap());
+        let received = || {
+            let (batch, count) = server.try_recv_batch::<4>();
+            let items = batch[..count]
+                .iter()
+                .map(|item| unsafe { item.assume_init_read() });
+            items.collect::<Vec<_>>()
+        };
+        assert_eq!(received(), ["0", "1", "2", "3"]);
+        assert_eq!(received(), ["4", "5"]);
+        assert_eq!(received(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn close_with_concurrent_receivers() {
+        for _ in 0..100 {
+            let (client, server) = duplex::<(), ()>();
+            let server = Arc::new(server);
+            let receivers: Vec<_> = (0..2)
+                .map(|_| {
+                    let server = server.clone();
+                    std::thread::spawn(move || server.recv())
+                })
+                .collect();
+            drop(client);
+            for receiver in receivers {
+                assert_eq!(receiver.join().unwrap(), None);
+            }
+        }
+    }
+}
@@ -0,0 +1,212 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use crate::synchronized::Waker;
+use crate::{Dequeue, SynchronizedQueue};
+
+/// Persistent consumer merging several source queues.
+///
+/// Sources are polled round-robin, starting after the last one which returned an item. When
+/// they are all empty, a single waker is registered in every source, so the first enqueue in
+/// any of them wakes the consumer; the copies left in the other sources are then already
+/// notified, and skipped by their producers like aborted wakers.
+pub struct FanIn<T> {
+    sources: Vec<Arc<SynchronizedQueue<T>>>,
+    next: usize,
+}
+
+impl<T> FanIn<T> {
+    pub fn new() -> Self {
+        FanIn {
+            sources: Vec::new(),
+            next: 0,
+        }
+    }
+
+    pub fn add(&mut self, source: Arc<SynchronizedQueue<T>>) {
+        self.sources.push(source);
+    }
+
+    /// Remove the source, compared by pointer, and return whether it was found.
+    pub fn remove(&mut self, source: &Arc<SynchronizedQueue<T>>) -> bool {
+        let len = self.sources.len();
+        self.sources.retain(|s| !Arc::ptr_eq(s, source));
+        self.sources.len() != len
+    }
+
+    pub fn sources(&self) -> &[Arc<SynchronizedQueue<T>>] {
+        &self.sources
+    }
+
+    pub fn try_recv(&mut self) -> Option<T> {
+        let len = self.sources.len();
+        for offset in 0..len {
+            let i = (self.next + offset) % len;
+            if let Dequeue::Data(value) = self.sources[i].try_dequeue() {
+                self.next = i + 1;
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    /// Abort the `registered` waker of a previous attempt, which has parked or returned
+    /// `Pending` since, then try to receive, registering a new waker if there is no item.
+    fn try_recv_or_register(
+        &mut self,
+        registered: &mut Option<Arc<Waker>>,
+        waker: impl FnOnce() -> Waker,
+    ) -> Option<T> {
+        if let Some(waker) = registered.take() {
+            waker.abort();
+        }
+        if let res @ Some(_) = self.try_recv() {
+            return res;
+        }
+        let waker = Arc::new(waker());
+        for source in &self.sources {
+            source.register_waker(waker.clone());
+        }
+        let res = self.try_recv();
+        match res {
+            Some(_) => {
+                waker.abort_unparked();
+            }
+            None => *registered = Some(waker),
+        }
+        res
+    }
+
+    /// Receive an item from any source, parking the thread until one is available.
+    ///
+    /// Without sources, it never returns.
+    pub fn recv(&mut self) -> T {
+        let mut registered = None;
+        loop {
+            if let Some(value) = self.try_recv_or_register(&mut registered, Waker::new_sync) {
+                return value;
+            }
+            std::thread::park();
+        }
+    }
+
+    /// Async version of [`recv`](FanIn::recv).
+    ///
+    /// Dropping the future aborts its registered waker; if it was already notified, a waiter
+    /// of each source is woken instead, as the notifying source is unknown.
+    pub fn recv_async(&mut self) -> impl Future<Output = T> + '_ {
+        RecvFuture {
+            fan_in: self,
+            waker: None,
+        }
+    }
+}
+
+struct RecvFuture<'a, T> {
+    fan_in: &'a mut FanIn<T>,
+    waker: Option<Arc<Waker>>,
+}
+
+impl<T> Future for RecvFuture<'_, T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let waker = || Waker::new_async(cx.waker().clone());
+        match this.fan_in.try_recv_or_register(&mut this.waker, waker) {
+            Some(value) => Poll::Ready(value),
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl<T> Drop for RecvFuture<'_, T> {
+    fn drop(&mut self) {
+        if self.waker.take().is_some_and(|waker| waker.abort()) {
+            for source in &self.fan_in.sources {
+                source.wake_waiters(1);
+            }
+        }
+    }
+}
+
+impl<T> Default for FanIn<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::sync::Arc;
+
+    use crate::{FanIn, SynchronizedQueue};
+
+    #[test]
+    fn fan_in() {
+        let sources: Vec<_> = (0..3).map(|_| Arc::new(SynchronizedQueue::new())).collect();
+        let mut fan_in = FanIn::new();
+        for source in &sources {
+            fan_in.add(source.clone());
+        }
+        let producers: Vec<_> = (0..3)
+            .map(|i| {
+                let source = sources[i].clone();
+                std::thread::spawn(move || (0..100).for_each(|j| source.enqueue(i * 100 + j)))
+            })
+            .collect();
+        let mut values = HashSet::new();
+        for k in 0..300 {
+            let value = if k % 2 == 0 {
+                fan_in.recv()
+            } else {
+                futures::executor::block_on(fan_in.recv_async())
+            };
+            values.insert(value);
+        }
+        producers.into_iter().for_each(|p| p.join().unwrap());
+        assert_eq!(values, (0..300).collect());
+        assert_eq!(fan_in.try_recv(), None);
+
+        assert!(fan_in.remove(&sources[1]));
+        assert!(!fan_in.remove(&sources[1]));
+        sources[1].enqueue(0);
+        assert_eq!(fan_in.try_recv(), None);
+        sources[2].enqueue(1);
+        assert_eq!(fan_in.recv(), 1);
+    }
+
+    #[test]
+    fn cancelled_recv_async() {
+        use std::future::Future;
+        use std::pin::pin;
+        use std::task::Context;
+        use std::time::{Duration, Instant};
+
+        let source = Arc::new(SynchronizedQueue::new());
+        let mut fan_in = FanIn::new();
+        fan_in.add(source.clone());
+        {
+            let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+            let mut recv = pin!(fan_in.recv_async());
+            assert!(recv.as_mut().poll(&mut cx).is_pending());
+        }
+        let receiver = std::thread::spawn(move || fan_in.recv());
+        while source.health().waiters < 2 {
+            std::thread::yield_now();
+        }
+        source.enqueue(42);
+        let start = Instant::now();
+        while !receiver.is_finished() {
+            assert!(
+                start.elapsed() < Duration::from_secs(10),
+                "receiver not woken"
+            );
+            std::thread::yield_now();
+        }
+        assert_eq!(receiver.join().unwrap(), 42);
+    }
+}
@@ -0,0 +1,136 @@
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{self, Ordering};
+
+use crate::Queue;
+
+/// Self-pipe written on the empty to non-empty transition, and drained on the opposite one.
+pub(crate) struct Readiness {
+    reader: UnixStream,
+    writer: UnixStream,
+}
+
+impl Readiness {
+    fn new() -> io::Result<Self> {
+        let (reader, writer) = UnixStream::pair()?;
+        reader.set_nonblocking(true)?;
+        writer.set_nonblocking(true)?;
+        Ok(Readiness { reader, writer })
+    }
+
+    pub(crate) fn notify(&self) {
+        // A full buffer already makes the fd readable.
+        let _ = (&self.writer).write(&[0]);
+    }
+
+    /// Read all the pending bytes, then notify again unless `is_empty`, as an enqueue may have
+    /// notified between the empty transition and the drain.
+    pub(crate) fn drain(&self, is_empty: impl FnOnce() -> bool) {
+        let mut buffer = [0; 64];
+        loop {
+            match (&self.reader).read(&mut buffer) {
+                Ok(n) if n > 0 => continue,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                _ => break,
+            }
+        }
+        atomic::fence(Ordering::SeqCst);
+        if !is_empty() {
+            self.notify();
+        }
+    }
+}
+
+impl<T> Queue<T> {
+    /// File descriptor readable while the queue is non-empty, to be registered in a `poll`/`epoll`
+    /// based event loop; it is created on first call, and owned by the queue.
+    ///
+    /// A byte is written on each empty to non-empty transition, and all pending bytes are read on
+    /// each non-empty to empty transition, after which the fd is notified again if items have
+    /// been enqueued concurrently. Once concurrent operations complete, the fd is thus readable
+    /// if the queue is non-empty. The converse does not hold: the fd may stay readable while
+    /// the queue is empty, e.g. when an item is enqueued and dequeued during the drain, so
+    /// consumers must handle [`Dequeue::Empty`](crate::Dequeue::Empty) after a wakeup. The fd
+    /// must not be read by the caller.
+    pub fn readiness_fd(&self) -> io::Result<RawFd> {
+        if let Some(readiness) = self.readiness.get() {
+            return Ok(readiness.reader.as_raw_fd());
+        }
+        let readiness = Readiness::new()?;
+        let mut created = false;
+        let readiness = self.readiness.get_or_init(|| {
+            created = true;
+            readiness
+        });
+        if created && !self.is_empty() {
+            readiness.notify();
+        }
+        Ok(readiness.reader.as_raw_fd())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::raw::{c_int, c_short, c_ulong};
+
+    use crate::Queue;
+
+    #[repr(C)]
+    struct PollFd {
+        fd: c_int,
+        events: c_short,
+        revents: c_short,
+    }
+
+    const POLLIN: c_short = 1;
+
+    extern "C" {
+        fn poll(fds: *mut PollFd, nfds: c_ulong, timeout: c_int) -> c_int;
+    }
+
+    fn is_ready(fd: c_int) -> bool {
+        let mut fds = [PollFd {
+            fd,
+            events: POLLIN,
+            revents: 0,
+        }];
+        let res = unsafe { poll(fds.as_mut_ptr(), 1, 0) };
+        assert!(res >= 0);
+        fds[0].revents & POLLIN != 0
+    }
+
+    #[test]
+    fn readiness_fd() {
+        let queue = Queue::new();
+        queue.enqueue(0);
+        let fd = queue.readiness_fd().unwrap();
+        assert_eq!(queue.readiness_fd().unwrap(), fd);
+        assert!(is_ready(fd));
+        queue.dequeue();
+        assert!(!is_ready(fd));
+        queue.enqueue(1);
+        queue.enqueue(2);
+        assert!(is_ready(fd));
+        queue.dequeue();
+        assert!(is_ready(fd));
+        queue.dequeue();
+        assert!(!is_ready(fd));
+    }
+
+    #[test]
+    fn readiness_drain() {
+        let queue = Queue::new();
+        let fd = queue.readiness_fd().unwrap();
+        let readiness = queue.readiness.get().unwrap();
+        queue.enqueue(0);
+        // notification of an enqueue racing with a previous empty transition
+        readiness.notify();
+        queue.dequeue();
+        assert!(!is_ready(fd));
+        readiness.drain(|| false);
+        assert!(is_ready(fd));
+        readiness.drain(|| true);
+        assert!(!is_ready(fd));
+    }
+}
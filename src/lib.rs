@@ -1,5 +1,40 @@
-pub use queue::{Dequeue, Queue};
-pub use synchronized::SynchronizedQueue;
+// Atomics of `core` are always lock-free, but only exist for the widths natively supported by
+// the target; fail with an explicit message rather than on the first missing type.
+#[cfg(not(target_has_atomic = "ptr"))]
+compile_error!("umpmc requires native pointer-width atomics");
+#[cfg(all(
+    any(feature = "metrics", feature = "timestamps"),
+    not(target_has_atomic = "64")
+))]
+compile_error!("the `metrics` and `timestamps` features of umpmc require native 64-bit atomics");
 
+pub use bytes::ByteQueue;
+pub use duplex::{duplex, Endpoint};
+pub use fan_in::FanIn;
+pub use permit::{Permit, PermitQueue};
+#[cfg(feature = "metrics")]
+pub use queue::EnqueueStats;
+pub use queue::{
+    Dequeue, Queue, QueueHealth, SpinReport, SpinStrategy, Stamp, MAX_REASONABLE_SPIN,
+};
+pub use scope::{ConsumeMode, ConsumeScope};
+pub use sink::QueueSink;
+pub use spin::SpinQueue;
+pub use spmc::{Consumer, Producer};
+pub use synchronized::{
+    BatchConsumed, Clock, DetailedDequeue, ParkAction, PollState, SynchronizedQueue, SystemClock,
+    Timer, Windowed,
+};
+
+mod bytes;
+mod duplex;
+mod fan_in;
+#[cfg(all(unix, feature = "fd"))]
+mod fd;
+mod permit;
 mod queue;
+mod scope;
+mod sink;
+mod spin;
+mod spmc;
 mod synchronized;
@@ -0,0 +1,136 @@
+use crate::SynchronizedQueue;
+
+/// Synchronized queue limiting the number of items processed concurrently.
+///
+/// Permits are units stored in an inner `SynchronizedQueue<()>`, so waiting for a permit uses
+/// the same waker machinery as waiting for data. A consumer never dropping its permits starves
+/// the others, and waiting for a permit while holding one can deadlock.
+pub struct PermitQueue<T> {
+    queue: SynchronizedQueue<T>,
+    permits: SynchronizedQueue<()>,
+}
+
+/// Permit of a [`PermitQueue`], released on drop.
+pub struct Permit<'a> {
+    permits: &'a SynchronizedQueue<()>,
+}
+
+impl<T> PermitQueue<T> {
+    pub fn new(permits: usize) -> Self {
+        let queue = PermitQueue {
+            queue: SynchronizedQueue::new(),
+            permits: SynchronizedQueue::new(),
+        };
+        (0..permits).for_each(|_| queue.permits.enqueue(()));
+        queue
+    }
+
+    pub fn enqueue(&self, value: T) {
+        self.queue.enqueue(value)
+    }
+
+    /// Wait for a permit, then for an item.
+    pub fn dequeue_permit(&self) -> (T, Permit<'_>) {
+        self.permits.dequeue();
+        let permit = Permit {
+            permits: &self.permits,
+        };
+        (self.queue.dequeue(), permit)
+    }
+
+    /// Async version of [`dequeue_permit`](PermitQueue::dequeue_permit).
+    ///
+    /// Dropping the future aborts its registered waker, so a cancelled wait does not swallow
+    /// the wakeup of a released permit or of an enqueued item.
+    pub async fn dequeue_permit_async(&self) -> (T, Permit<'_>) {
+        self.permits.dequeue_async().await;
+        let permit = Permit {
+            permits: &self.permits,
+        };
+        (self.queue.dequeue_async().await, permit)
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.permits.enqueue(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::PermitQueue;
+
+    #[test]
+    fn permits() {
+        let queue = Arc::new(PermitQueue::new(2));
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+        let consumers: Vec<_> = (0..4)
+            .map(|_| {
+                let (queue, in_flight, max_in_flight) =
+                    (queue.clone(), in_flight.clone(), max_in_flight.clone());
+                std::thread::spawn(move || loop {
+                    let (value, _permit) = queue.dequeue_permit();
+                    let Some(_) = value else { break };
+                    let count = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_in_flight.fetch_max(count, Ordering::SeqCst);
+                    std::thread::sleep(Duration::from_millis(1));
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+                })
+            })
+            .collect();
+        for i in 0..20 {
+            queue.enqueue(Some(i));
+        }
+        for _ in 0..4 {
+            queue.enqueue(None);
+        }
+        consumers.into_iter().for_each(|c| c.join().unwrap());
+        assert!(max_in_flight.load(Ordering::SeqCst) <= 2);
+        let permit = futures::executor::block_on(async {
+            queue.enqueue(Some(20));
+            queue.dequeue_permit_async().await
+        });
+        assert_eq!(permit.0, Some(20));
+    }
+
+    #[test]
+    fn cancelled_permit_wait() {
+        use std::future::Future;
+        use std::pin::pin;
+        use std::task::Context;
+        use std::time::Instant;
+
+        let queue = Arc::new(PermitQueue::new(1));
+        queue.enqueue(0);
+        let (_, held) = queue.dequeue_permit();
+        {
+            let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+            let mut cancelled = pin!(queue.dequeue_permit_async());
+            assert!(cancelled.as_mut().poll(&mut cx).is_pending());
+        }
+        queue.enqueue(1);
+        let consumer = {
+            let queue = queue.clone();
+            std::thread::spawn(move || queue.dequeue_permit().0)
+        };
+        while queue.permits.health().waiters < 2 {
+            std::thread::yield_now();
+        }
+        drop(held);
+        let start = Instant::now();
+        while !consumer.is_finished() {
+            assert!(
+                start.elapsed() < Duration::from_secs(10),
+                "consumer not woken"
+            );
+            std::thread::yield_now();
+        }
+        assert_eq!(consumer.join().unwrap(), 1);
+    }
+}
@@ -1,6 +1,26 @@
 use std::mem::MaybeUninit;
+use std::ops::Range;
+use std::panic::{AssertUnwindSafe, RefUnwindSafe, UnwindSafe};
 use std::ptr::NonNull;
+#[cfg(any(feature = "metrics", feature = "timestamps"))]
+use std::sync::atomic::AtomicU64;
 use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+/// Upper bound of the `spin` parameter accepted by dequeue methods, checked in debug builds.
+///
+/// `spin` counts spin loop iterations, each lasting a few nanoseconds, and is only meant to wait
+/// for concurrent enqueues to complete; values from 0 to a few hundreds are recommended.
+pub const MAX_REASONABLE_SPIN: usize = 1 << 16;
+
+const CLOCK_CHECK_INTERVAL: usize = 64;
+const AUTO_SPIN_MAX: usize = 1 << 10;
+const AUTO_SPIN_SMOOTHING: usize = 8;
+const PAUSE_THEN_YIELD_PAUSES: usize = 64;
+
+type Callback = Box<dyn Fn() + Send + Sync>;
+type DropHandler<T> = Box<dyn Fn(T) + Send + Sync>;
 
 struct NodeIndex {
     value: MaybeUninit<usize>,
@@ -37,6 +57,8 @@ struct Node<T> {
     index: NodeIndex,
     prev: *mut Node<T>,
     next: AtomicPtr<Node<T>>,
+    #[cfg(feature = "timestamps")]
+    enqueued_at: AtomicU64,
 }
 
 impl<T> Node<T> {
@@ -46,24 +68,83 @@ impl<T> Node<T> {
             index: NodeIndex::new(),
             prev: std::ptr::null_mut(),
             next: AtomicPtr::new(std::ptr::null_mut()),
+            #[cfg(feature = "timestamps")]
+            enqueued_at: AtomicU64::new(0),
         }
     }
 }
 
-struct Cache<T> {
+static NEXT_QUEUE_ID: AtomicUsize = AtomicUsize::new(0);
+static NEXT_CACHE_SHARD: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static CACHE_SHARD: usize = NEXT_CACHE_SHARD.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Stack of recycled nodes, with its node count, aligned so that shards do not share a cache
+/// line.
+#[repr(align(128))]
+struct CacheShard<T> {
     head: AtomicPtr<Node<T>>,
+    len: AtomicUsize,
+}
+
+/// Stacks of recycled nodes; each thread puts nodes into its own shard, and pops from its shard
+/// first, so recycling does not serialize all threads on a single stack head.
+struct Cache<T> {
+    shards: Box<[CacheShard<T>]>,
+    alloc_batch: usize,
+    /// Contiguous block of nodes allocated by [`Queue::defragment`], only freed as a whole.
+    slab: Option<NonNull<[Node<T>]>>,
+    #[cfg(feature = "metrics")]
+    hits: AtomicU64,
+    #[cfg(feature = "metrics")]
+    misses: AtomicU64,
 }
 
 impl<T> Cache<T> {
     fn new() -> Self {
+        Self::with_shards(1)
+    }
+    fn with_shards(shards: usize) -> Self {
+        assert!(shards > 0, "cache must have at least one shard");
         Cache {
-            head: AtomicPtr::new(std::ptr::null_mut()),
+            shards: (0..shards)
+                .map(|_| CacheShard {
+                    head: AtomicPtr::new(std::ptr::null_mut()),
+                    len: AtomicUsize::new(0),
+                })
+                .collect(),
+            alloc_batch: 1,
+            slab: None,
+            #[cfg(feature = "metrics")]
+            hits: AtomicU64::new(0),
+            #[cfg(feature = "metrics")]
+            misses: AtomicU64::new(0),
+        }
+    }
+    fn shard(&self) -> usize {
+        match self.shards.len() {
+            1 => 0,
+            len => CACHE_SHARD.with(|shard| *shard % len),
         }
     }
     fn pop(&self) -> *mut Node<T> {
-        let mut head = self.head.load(Ordering::Relaxed);
+        let start = self.shard();
+        for i in 0..self.shards.len() {
+            let shard = &self.shards[(start + i) % self.shards.len()];
+            let node = Self::pop_shard(shard);
+            if !node.is_null() {
+                shard.len.fetch_sub(1, Ordering::Relaxed);
+                return node;
+            }
+        }
+        std::ptr::null_mut()
+    }
+    fn pop_shard(shard: &CacheShard<T>) -> *mut Node<T> {
+        let mut head = shard.head.load(Ordering::Relaxed);
         while !head.is_null() {
-            match self.head.compare_exchange_weak(
+            match shard.head.compare_exchange_weak(
                 head,
                 unsafe { &*head }.prev,
                 Ordering::SeqCst,
@@ -76,16 +157,33 @@ impl<T> Cache<T> {
         std::ptr::null_mut()
     }
     fn get(&self) -> NonNull<Node<T>> {
-        match NonNull::new(self.pop()) {
+        let node = NonNull::new(self.pop());
+        #[cfg(feature = "metrics")]
+        match node {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        match node {
             Some(node) => node,
-            None => unsafe { NonNull::new_unchecked(Box::into_raw(Box::new(Node::new()))) },
+            None => {
+                for _ in 1..self.alloc_batch {
+                    self.put(Self::alloc());
+                }
+                Self::alloc()
+            }
         }
     }
+    fn alloc() -> NonNull<Node<T>> {
+        unsafe { NonNull::new_unchecked(Box::into_raw(Box::new(Node::new()))) }
+    }
     fn put(&self, node: NonNull<Node<T>>) {
-        let mut head = self.head.load(Ordering::Relaxed);
+        let shard = &self.shards[self.shard()];
+        // counted before the push, so that a concurrent pop never decrements below zero
+        shard.len.fetch_add(1, Ordering::Relaxed);
+        let mut head = shard.head.load(Ordering::Relaxed);
         loop {
             unsafe { &mut *node.as_ptr() }.prev = head;
-            match self.head.compare_exchange_weak(
+            match shard.head.compare_exchange_weak(
                 head,
                 node.as_ptr(),
                 Ordering::SeqCst,
@@ -96,19 +194,206 @@ impl<T> Cache<T> {
             }
         }
     }
+    fn len(&self) -> usize {
+        let lens = self.shards.iter().map(|s| s.len.load(Ordering::Relaxed));
+        lens.sum()
+    }
+    fn in_slab(&self, node: NonNull<Node<T>>) -> bool {
+        self.slab.is_some_and(|slab| {
+            let start = slab.as_ptr() as *mut Node<T>;
+            (start..start.wrapping_add(slab.len())).contains(&node.as_ptr())
+        })
+    }
     fn clear(&self) {
         while let Some(node) = NonNull::new(self.pop()) {
-            unsafe { Box::from_raw(node.as_ptr()) };
+            if !self.in_slab(node) {
+                drop(unsafe { Box::from_raw(node.as_ptr()) });
+            }
+        }
+    }
+    /// Free every node, which must all be cached, and fill the cache with a new slab of `len`
+    /// nodes, popped in address order.
+    fn replace_slab(&mut self, len: usize) {
+        self.clear();
+        if let Some(slab) = self.slab.take() {
+            drop(unsafe { Box::from_raw(slab.as_ptr()) });
+        }
+        if len == 0 {
+            return;
+        }
+        let slab: Box<[Node<T>]> = (0..len).map(|_| Node::new()).collect();
+        let slab = NonNull::from(Box::leak(slab));
+        self.slab = Some(slab);
+        let start = slab.as_ptr() as *mut Node<T>;
+        for i in (0..len).rev() {
+            self.put(unsafe { NonNull::new_unchecked(start.add(i)) });
         }
     }
 }
 
 impl<T> Drop for Cache<T> {
     fn drop(&mut self) {
-        self.clear()
+        self.replace_slab(0)
+    }
+}
+
+/// Spin count adapted to recent dequeue results, using an exponential moving average.
+///
+/// `Inconsistent` results, i.e. producers caught mid-enqueue, pull the average toward
+/// `AUTO_SPIN_MAX`, while `Empty` results pull it toward zero; `Data` results leave it
+/// unchanged. Concurrent updates may be lost, which only makes the average less accurate.
+struct AutoSpin {
+    average: AtomicUsize,
+}
+
+impl AutoSpin {
+    fn new() -> Self {
+        AutoSpin {
+            average: AtomicUsize::new(0),
+        }
+    }
+
+    fn spin(&self) -> usize {
+        self.average.load(Ordering::Relaxed)
+    }
+
+    fn update<T>(&self, res: &Dequeue<T>) {
+        let target = match res {
+            Dequeue::Empty => 0,
+            Dequeue::Inconsistent => AUTO_SPIN_MAX,
+            Dequeue::Data(_) => return,
+        };
+        let average = self.average.load(Ordering::Relaxed);
+        let average = if target > average {
+            average + (target - average).div_ceil(AUTO_SPIN_SMOOTHING)
+        } else {
+            average - (average - target).div_ceil(AUTO_SPIN_SMOOTHING)
+        };
+        self.average.store(average, Ordering::Relaxed);
+    }
+}
+
+/// Instruction executed at each iteration of dequeue spin loops.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default, Hash)]
+pub enum SpinStrategy {
+    /// [`std::hint::spin_loop`], e.g. PAUSE on x86 or YIELD on ARM.
+    #[default]
+    Pause,
+    /// [`std::thread::yield_now`], giving the core to another thread, e.g. an SMT sibling or
+    /// the preempted producer.
+    Yield,
+    /// Pause for the first iterations, then yield.
+    PauseThenYield,
+    /// Busy loop without hint.
+    Noop,
+}
+
+impl SpinStrategy {
+    fn hint(self, iteration: usize) {
+        match self {
+            SpinStrategy::Pause => std::hint::spin_loop(),
+            SpinStrategy::Yield => std::thread::yield_now(),
+            SpinStrategy::PauseThenYield if iteration < PAUSE_THEN_YIELD_PAUSES => {
+                std::hint::spin_loop()
+            }
+            SpinStrategy::PauseThenYield => std::thread::yield_now(),
+            SpinStrategy::Noop => {}
+        }
+    }
+
+    fn spin_until(self, spin: usize, spins: &mut usize, mut ready: impl FnMut() -> bool) {
+        for i in 0..spin {
+            if ready() {
+                break;
+            }
+            *spins += 1;
+            self.hint(i);
+        }
     }
 }
 
+fn without_index<T>(res: Dequeue<(usize, T)>) -> Dequeue<T> {
+    match res {
+        Dequeue::Empty => Dequeue::Empty,
+        Dequeue::Inconsistent => Dequeue::Inconsistent,
+        Dequeue::Data((_, value)) => Dequeue::Data(value),
+    }
+}
+
+/// Position of an item enqueued with [`Queue::enqueue_stamped`].
+///
+/// Stamps of a same queue are ordered like its items (modulo index wrapping), and stamps of
+/// different queues never compare equal. Ordering by `(queue_id, index)` is thus a total order,
+/// consistent with each queue order, but with no relation to the enqueuing time across queues.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Hash)]
+pub struct Stamp {
+    /// Unique id of the queue, see [`Queue::id`].
+    pub queue_id: usize,
+    /// Index of the item in the queue.
+    pub index: usize,
+}
+
+/// Length counter of a queue created with [`Queue::with_len_counter`].
+struct LenCounter {
+    len: AtomicUsize,
+    max_depth: AtomicUsize,
+}
+
+/// Snapshot of the observability counters, returned by [`Queue::health`] and
+/// [`SynchronizedQueue::health`](crate::SynchronizedQueue::health).
+///
+/// Fields are read independently with relaxed ordering, so they may not be mutually consistent
+/// under concurrent operations.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default, Hash)]
+pub struct QueueHealth {
+    /// `None` without the length counter, see [`Queue::with_len_counter`].
+    pub approx_len: Option<usize>,
+    /// `None` without the length counter, see [`Queue::with_len_counter`].
+    pub max_depth: Option<usize>,
+    /// Number of registered waiters, including aborted ones not yet skipped by producers;
+    /// always zero for a plain [`Queue`].
+    pub waiters: usize,
+    /// Number of allocated nodes available in the node cache; it may transiently exceed the
+    /// actual number under concurrent operations.
+    pub cached_nodes: usize,
+    /// `None` without the `metrics` feature.
+    pub total_enqueued: Option<u64>,
+    /// `None` without the `metrics` feature.
+    pub total_dequeued: Option<u64>,
+}
+
+/// Enqueue contention counters returned by [`Queue::enqueue_stats`].
+#[cfg(feature = "metrics")]
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default, Hash)]
+pub struct EnqueueStats {
+    /// Number of enqueued items.
+    pub enqueues: u64,
+    /// Number of failed compare-and-swap of the queue head, i.e. producer contention.
+    pub cas_retries: u64,
+    /// Number of nodes walked back to find the index of a previous item, which happens when
+    /// producers link concurrently.
+    pub index_walk_steps: u64,
+}
+
+#[cfg(feature = "metrics")]
+#[derive(Default)]
+struct EnqueueCounters {
+    total_enqueued: AtomicU64,
+    total_dequeued: AtomicU64,
+    enqueued_at_reset: AtomicU64,
+    cas_retries: AtomicU64,
+    index_walk_steps: AtomicU64,
+}
+
+/// Spinning done by [`Queue::dequeue_spin_report`].
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default, Hash)]
+pub struct SpinReport {
+    /// Number of spin loop iterations executed.
+    pub spins: usize,
+    /// Whether the result was obtained without spinning.
+    pub fast_path: bool,
+}
+
 #[derive(Copy, Clone, PartialEq, PartialOrd, Eq, Ord, Debug, Hash)]
 pub enum Dequeue<T> {
     Empty,
@@ -125,32 +410,407 @@ impl<T> Dequeue<T> {
     }
 }
 
+#[allow(clippy::from_over_into)]
 impl<T> Into<Option<T>> for Dequeue<T> {
     fn into(self) -> Option<T> {
         self.data()
     }
 }
 
+/// Unbounded lock-free MPMC queue.
+///
+/// The queue is lock-free as it only relies on pointer-width atomics, plus 64-bit ones with the
+/// `metrics` and `timestamps` features, which the crate requires to be natively supported by
+/// the target at build time.
+///
+/// Dropping the queue drains and frees its nodes, which requires unique ownership; when the
+/// queue is shared with an `Arc`, it is only dropped with the last reference, i.e. after every
+/// producer and consumer is gone, so no `enqueue` can run concurrently with the drop.
+///
+/// Items are only handed out by value, so the queue is `Send` and `Sync` as long as `T: Send`;
+/// it cannot be shared across threads otherwise:
+/// ```compile_fail
+/// use std::rc::Rc;
+/// use std::sync::Arc;
+///
+/// let queue = Arc::new(umpmc::Queue::<Rc<()>>::new());
+/// std::thread::spawn(move || queue.enqueue(Rc::new(())));
+/// ```
+///
+/// Nodes never point back to the queue, so a queue can be moved as long as it is not borrowed,
+/// and its address is stable while borrowed. It can thus be shared with foreign code through
+/// a pointer, e.g. from `Box::into_raw` or a pinned box, and `extern "C"` shims calling its
+/// methods. Its layout is not `#[repr(C)]` and must be treated as opaque by foreign code.
 pub struct Queue<T> {
+    id: usize,
     head: AtomicPtr<Node<T>>,
     tail: AtomicPtr<Node<T>>,
     index: AtomicUsize,
+    len: Option<LenCounter>,
     cache: Cache<T>,
+    auto_spin: Option<AutoSpin>,
+    spin_strategy: SpinStrategy,
+    on_nonempty: OnceLock<Callback>,
+    on_empty: OnceLock<Callback>,
+    high_watermark: OnceLock<(usize, Callback)>,
+    #[cfg(feature = "metrics")]
+    enqueue_counters: EnqueueCounters,
+    drop_handler: Option<DropHandler<T>>,
+    #[cfg(feature = "timestamps")]
+    epoch: Instant,
+    #[cfg(feature = "timestamps")]
+    ttl: Option<Duration>,
+    #[cfg(debug_assertions)]
+    dropping: AtomicBool,
+    #[cfg(all(unix, feature = "fd"))]
+    pub(crate) readiness: OnceLock<crate::fd::Readiness>,
 }
 
+unsafe impl<T: Send> Send for Queue<T> {}
+unsafe impl<T: Send> Sync for Queue<T> {}
+impl<T: UnwindSafe> UnwindSafe for Queue<T> {}
+impl<T: RefUnwindSafe> RefUnwindSafe for Queue<T> {}
+
 impl<T> Queue<T> {
     pub fn new() -> Self {
         Queue {
+            id: NEXT_QUEUE_ID.fetch_add(1, Ordering::Relaxed),
             head: AtomicPtr::new(std::ptr::null_mut()),
             tail: AtomicPtr::new(std::ptr::null_mut()),
             index: AtomicUsize::new(0),
+            len: None,
             cache: Cache::new(),
+            auto_spin: None,
+            spin_strategy: SpinStrategy::Pause,
+            on_nonempty: OnceLock::new(),
+            on_empty: OnceLock::new(),
+            high_watermark: OnceLock::new(),
+            #[cfg(feature = "metrics")]
+            enqueue_counters: EnqueueCounters::default(),
+            drop_handler: None,
+            #[cfg(feature = "timestamps")]
+            epoch: Instant::now(),
+            #[cfg(feature = "timestamps")]
+            ttl: None,
+            #[cfg(debug_assertions)]
+            dropping: AtomicBool::new(false),
+            #[cfg(all(unix, feature = "fd"))]
+            readiness: OnceLock::new(),
+        }
+    }
+
+    /// Create a queue counting its items, as required by [`approx_len`](Queue::approx_len)
+    /// and the methods relying on it.
+    ///
+    /// Every enqueue and dequeue then updates the shared counter, which is why the other
+    /// queues do not maintain it.
+    pub fn with_len_counter() -> Self {
+        let mut queue = Self::new();
+        queue.len = Some(LenCounter {
+            len: AtomicUsize::new(0),
+            max_depth: AtomicUsize::new(0),
+        });
+        queue
+    }
+
+    /// Create a queue whose node cache is split into `shards` stacks, reducing contention on
+    /// node recycling when many threads enqueue and dequeue concurrently.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `shards` is zero.
+    pub fn with_cache_shards(shards: usize) -> Self {
+        let mut queue = Self::new();
+        queue.cache = Cache::with_shards(shards);
+        queue
+    }
+
+    /// Create a queue allocating `batch` nodes at once when its node cache is empty, caching the
+    /// unused ones, so that a burst after a cold start hits the allocator less often.
+    ///
+    /// Nodes are still allocated one by one, so that they can be freed individually.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `batch` is zero.
+    pub fn with_alloc_batch(batch: usize) -> Self {
+        assert!(batch > 0, "allocation batch must not be empty");
+        let mut queue = Self::new();
+        queue.cache.alloc_batch = batch;
+        queue
+    }
+
+    /// Create a queue with `capacity` nodes already allocated in its node cache.
+    ///
+    /// The nodes are not touched beyond their allocation, see [`prefault`](Queue::prefault).
+    pub fn with_capacity(capacity: usize) -> Self {
+        let queue = Self::new();
+        (0..capacity).for_each(|_| queue.cache.put(Cache::alloc()));
+        queue
+    }
+
+    /// Create a queue whose dequeue methods drop the items which have been queued for more than
+    /// `ttl`, and return the next one instead.
+    ///
+    /// Expiry is lazy: expired items are only checked, and dropped in the consumer thread,
+    /// when they are dequeued, so they still count in [`approx_len`](Queue::approx_len) until
+    /// then. If the last item is expired, `Empty` is returned.
+    #[cfg(feature = "timestamps")]
+    pub fn with_ttl(ttl: Duration) -> Self {
+        let mut queue = Self::new();
+        queue.ttl = Some(ttl);
+        queue
+    }
+
+    /// Create a queue whose `dequeue` adapts its spin count to recent results: it spins more
+    /// after `Inconsistent` results, and less after `Empty` ones.
+    pub fn with_auto_spin() -> Self {
+        let mut queue = Self::new();
+        queue.auto_spin = Some(AutoSpin::new());
+        queue
+    }
+
+    /// Create a queue whose dequeue methods use `strategy` in their spin loops.
+    pub fn with_spin_strategy(strategy: SpinStrategy) -> Self {
+        let mut queue = Self::new();
+        queue.spin_strategy = strategy;
+        queue
+    }
+
+    /// Create a queue passing the items remaining at drop to `handler`, e.g. to report
+    /// stranded messages, instead of just dropping them.
+    ///
+    /// A panic in the handler does not prevent it from being called with the next items; the
+    /// first panic is resumed once the queue is drained, unless the thread is already panicking.
+    pub fn with_drop_handler(handler: impl Fn(T) + Send + Sync + 'static) -> Self {
+        let mut queue = Self::new();
+        queue.drop_handler = Some(Box::new(handler));
+        queue
+    }
+
+    /// Build a queue from a vector, linking the nodes directly without atomic operations.
+    pub fn from_vec(items: Vec<T>) -> Self {
+        let mut queue = Queue::new();
+        #[cfg(feature = "metrics")]
+        {
+            *queue.enqueue_counters.total_enqueued.get_mut() = items.len() as u64;
+        }
+        queue.link_vec(items, 0);
+        queue
+    }
+
+    fn link_vec(&mut self, items: Vec<T>, first_index: usize) {
+        debug_assert!(self.head.get_mut().is_null());
+        *self.index.get_mut() = first_index;
+        if let Some(counter) = &mut self.len {
+            *counter.len.get_mut() = items.len();
+        }
+        let mut prev: *mut Node<T> = std::ptr::null_mut();
+        for (offset, value) in items.into_iter().enumerate() {
+            let node = unsafe { self.cache.get().as_mut() };
+            node.value.write(value);
+            #[cfg(feature = "timestamps")]
+            self.stamp(node);
+            node.index.set(first_index.wrapping_add(offset));
+            node.prev = prev;
+            match unsafe { prev.as_mut() } {
+                Some(prev) => *prev.next.get_mut() = node,
+                None => *self.tail.get_mut() = node,
+            }
+            prev = node;
+        }
+        *self.head.get_mut() = prev;
+    }
+
+    /// Reallocate the queued nodes in order into a contiguous block and free the cached ones,
+    /// to improve the memory locality of a long-lived queue; it requires exclusive access, i.e.
+    /// a quiescent queue.
+    ///
+    /// The block is only freed as a whole, by the next defragmentation or the queue drop, so
+    /// its nodes stay allocated in the meantime, cached when not used.
+    ///
+    /// Items keep their order and their indices; transition callbacks are not invoked.
+    pub fn defragment(&mut self) {
+        let first_index = *self.index.get_mut();
+        let on_empty = self.on_empty.take();
+        let mut items = Vec::new();
+        self.dequeue_into(&mut items, usize::MAX);
+        // relinked items are neither dequeued nor enqueued again
+        #[cfg(feature = "metrics")]
+        {
+            *self.enqueue_counters.total_dequeued.get_mut() -= items.len() as u64;
+        }
+        self.cache.replace_slab(items.len());
+        self.link_vec(items, first_index);
+        #[cfg(all(unix, feature = "fd"))]
+        if let Some(readiness) = self.readiness.get().filter(|_| !self.is_empty()) {
+            readiness.notify();
+        }
+        if let Some(on_empty) = on_empty {
+            self.on_empty = OnceLock::from(on_empty);
+        }
+    }
+
+    fn len_counter(&self) -> &LenCounter {
+        (self.len.as_ref()).expect("queue created without length counter")
+    }
+
+    /// Number of items in the queue; it can be outdated as soon as it is returned.
+    ///
+    /// The counter is incremented before an item is published and decremented after it is
+    /// dequeued, so it may include items being enqueued or dequeued concurrently.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the queue was not created with [`with_len_counter`](Queue::with_len_counter).
+    pub fn approx_len(&self) -> usize {
+        self.len_counter().len.load(Ordering::Relaxed)
+    }
+
+    /// Whether the queue holds no published item; it can be outdated as soon as it is
+    /// returned, and it does not require the length counter.
+    pub fn is_empty(&self) -> bool {
+        self.tail.load(Ordering::Acquire).is_null()
+    }
+
+    /// Largest [`approx_len`](Queue::approx_len) observed by an enqueue since creation or
+    /// the last [`reset_max_depth`](Queue::reset_max_depth).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the queue was not created with [`with_len_counter`](Queue::with_len_counter).
+    pub fn max_depth(&self) -> usize {
+        self.len_counter().max_depth.load(Ordering::Relaxed)
+    }
+
+    /// Reset the high-water mark to the current length, to start a new measurement window.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the queue was not created with [`with_len_counter`](Queue::with_len_counter).
+    pub fn reset_max_depth(&self) {
+        let counter = self.len_counter();
+        let len = counter.len.load(Ordering::Relaxed);
+        counter.max_depth.store(len, Ordering::Relaxed);
+    }
+
+    /// Ensure at least `n` nodes are cached, and write to their memory, so that the pages
+    /// holding them are resident before a latency-critical phase.
+    ///
+    /// Cached nodes are temporarily removed from the cache, so concurrent enqueues may allocate;
+    /// it should be called before the hot phase. It only prevents first-touch page faults: pages
+    /// may still be swapped out later, as memory is not locked.
+    pub fn prefault(&self, n: usize) {
+        let nodes: Vec<_> = (0..n)
+            .map(|_| NonNull::new(self.cache.pop()).unwrap_or_else(Cache::alloc))
+            .collect();
+        for node in nodes {
+            unsafe {
+                let value = std::ptr::addr_of_mut!((*node.as_ptr()).value);
+                value.write_bytes(0, 1);
+            }
+            self.cache.put(std::hint::black_box(node));
+        }
+    }
+
+    /// Return a snapshot of the queue observability counters, see [`QueueHealth`].
+    pub fn health(&self) -> QueueHealth {
+        QueueHealth {
+            approx_len: (self.len.as_ref()).map(|c| c.len.load(Ordering::Relaxed)),
+            max_depth: (self.len.as_ref()).map(|c| c.max_depth.load(Ordering::Relaxed)),
+            waiters: 0,
+            cached_nodes: self.cache.len(),
+            #[cfg(feature = "metrics")]
+            total_enqueued: Some(self.total_enqueued()),
+            #[cfg(not(feature = "metrics"))]
+            total_enqueued: None,
+            #[cfg(feature = "metrics")]
+            total_dequeued: Some(self.total_dequeued()),
+            #[cfg(not(feature = "metrics"))]
+            total_dequeued: None,
+        }
+    }
+
+    fn new_node(&self, value: T) -> NonNull<Node<T>> {
+        #[cfg(debug_assertions)]
+        assert!(
+            !self.dropping.load(Ordering::Relaxed),
+            "enqueue into a queue being dropped"
+        );
+        #[cfg(feature = "metrics")]
+        self.enqueue_counters
+            .total_enqueued
+            .fetch_add(1, Ordering::Relaxed);
+        let mut node = self.cache.get();
+        unsafe { node.as_mut() }.value.write(value);
+        #[cfg(feature = "timestamps")]
+        self.stamp(unsafe { node.as_ref() });
+        node
+    }
+
+    /// Count `count` items about to be published, returning the high watermark callback if
+    /// they make the length cross its threshold; it must be run once they are published.
+    fn count_enqueued(&self, count: usize) -> Option<&Callback> {
+        let counter = self.len.as_ref()?;
+        // sequentially consistent for `SynchronizedQueue` length waiters
+        let len = counter.len.fetch_add(count, Ordering::SeqCst) + count;
+        if len > counter.max_depth.load(Ordering::Relaxed) {
+            counter.max_depth.fetch_max(len, Ordering::Relaxed);
+        }
+        let (threshold, f) = self.high_watermark.get()?;
+        (len - count < *threshold && *threshold <= len).then_some(f)
+    }
+
+    #[cfg(feature = "timestamps")]
+    fn stamp(&self, node: &Node<T>) {
+        let nanos = self.epoch.elapsed().as_nanos() as u64;
+        node.enqueued_at.store(nanos, Ordering::Relaxed);
+    }
+
+    #[cfg_attr(not(feature = "timestamps"), allow(unused_variables))]
+    fn is_expired(&self, node: &Node<T>) -> bool {
+        #[cfg(feature = "timestamps")]
+        if let Some(ttl) = self.ttl {
+            let enqueued_at = Duration::from_nanos(node.enqueued_at.load(Ordering::Relaxed));
+            return self.epoch.elapsed().saturating_sub(enqueued_at) > ttl;
         }
+        false
+    }
+
+    /// Time spent in the queue by the next item to dequeue, or `None` if the queue is empty.
+    ///
+    /// The result is racy: the item may be dequeued concurrently, and its node reused by a more
+    /// recent item, in which case a shorter age is returned. Items linked by
+    /// [`from_vec`](Queue::from_vec) or [`defragment`](Queue::defragment) are stamped at that
+    /// time.
+    #[cfg(feature = "timestamps")]
+    pub fn oldest_age(&self) -> Option<Duration> {
+        // nodes are only freed with exclusive access, so the tail stays readable
+        let tail = unsafe { self.tail.load(Ordering::Acquire).as_ref() }?;
+        let enqueued_at = Duration::from_nanos(tail.enqueued_at.load(Ordering::Relaxed));
+        Some(self.epoch.elapsed().saturating_sub(enqueued_at))
+    }
+
+    /// Id assigned at construction, unique among the queues of the process.
+    pub fn id(&self) -> usize {
+        self.id
     }
 
     pub fn enqueue(&self, value: T) {
-        let node = unsafe { self.cache.get().as_mut() };
-        node.value.write(value);
+        self.enqueue_node(value);
+    }
+
+    /// Enqueue the value, returning its [`Stamp`].
+    pub fn enqueue_stamped(&self, value: T) -> Stamp {
+        Stamp {
+            queue_id: self.id,
+            index: self.enqueue_node(value),
+        }
+    }
+
+    fn enqueue_node(&self, value: T) -> usize {
+        let node = unsafe { self.new_node(value).as_mut() };
+        let high_watermark = self.count_enqueued(1);
         let mut head = self.head.load(Ordering::Relaxed);
         loop {
             node.prev = head;
@@ -159,36 +819,303 @@ impl<T> Queue<T> {
                 .compare_exchange_weak(head, node, Ordering::SeqCst, Ordering::Relaxed)
             {
                 Ok(_) => break,
-                Err(h) => head = h,
+                Err(h) => {
+                    head = h;
+                    self.count_cas_retry();
+                }
+            }
+        }
+        let index = self.link(node, head);
+        if let Some(f) = high_watermark {
+            f();
+        }
+        index
+    }
+
+    pub(crate) fn enqueue_exclusive(&self, value: T) {
+        let node = unsafe { self.new_node(value).as_mut() };
+        let high_watermark = self.count_enqueued(1);
+        let head = self.head.swap(node, Ordering::SeqCst);
+        node.prev = head;
+        self.link(node, head);
+        if let Some(f) = high_watermark {
+            f();
+        }
+    }
+
+    /// Return the node cache `(hits, misses)` counts since creation or the last
+    /// [`reset_cache_stats`](Queue::reset_cache_stats); a miss allocates a new node.
+    #[cfg(feature = "metrics")]
+    pub fn cache_stats(&self) -> (u64, u64) {
+        (
+            self.cache.hits.load(Ordering::Relaxed),
+            self.cache.misses.load(Ordering::Relaxed),
+        )
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn reset_cache_stats(&self) {
+        self.cache.hits.store(0, Ordering::Relaxed);
+        self.cache.misses.store(0, Ordering::Relaxed);
+    }
+
+    /// Return the enqueue contention counters since creation or the last
+    /// [`reset_enqueue_stats`](Queue::reset_enqueue_stats).
+    #[cfg(feature = "metrics")]
+    pub fn enqueue_stats(&self) -> EnqueueStats {
+        let counters = &self.enqueue_counters;
+        let enqueued_at_reset = counters.enqueued_at_reset.load(Ordering::Relaxed);
+        EnqueueStats {
+            enqueues: self.total_enqueued().wrapping_sub(enqueued_at_reset),
+            cas_retries: counters.cas_retries.load(Ordering::Relaxed),
+            index_walk_steps: counters.index_walk_steps.load(Ordering::Relaxed),
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn reset_enqueue_stats(&self) {
+        let counters = &self.enqueue_counters;
+        let total_enqueued = self.total_enqueued();
+        counters
+            .enqueued_at_reset
+            .store(total_enqueued, Ordering::Relaxed);
+        counters.cas_retries.store(0, Ordering::Relaxed);
+        counters.index_walk_steps.store(0, Ordering::Relaxed);
+    }
+
+    /// Number of items enqueued since creation; it is monotonic, and would only wrap after
+    /// 2^64 enqueues. Together with [`total_dequeued`](Queue::total_dequeued), it gives the
+    /// throughput and cross-checks [`approx_len`](Queue::approx_len).
+    #[cfg(feature = "metrics")]
+    pub fn total_enqueued(&self) -> u64 {
+        self.enqueue_counters.total_enqueued.load(Ordering::Relaxed)
+    }
+
+    /// Number of items dequeued since creation, see [`total_enqueued`](Queue::total_enqueued).
+    #[cfg(feature = "metrics")]
+    pub fn total_dequeued(&self) -> u64 {
+        self.enqueue_counters.total_dequeued.load(Ordering::Relaxed)
+    }
+
+    fn count_cas_retry(&self) {
+        #[cfg(feature = "metrics")]
+        self.enqueue_counters
+            .cas_retries
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Enqueue all the values of `iter` as a contiguous run.
+    ///
+    /// The nodes are linked locally and published with a single head compare-and-swap, so
+    /// items of other producers cannot be interleaved within the batch. Consumers may still
+    /// get `Inconsistent` while the indices of the batch are being assigned.
+    pub fn enqueue_atomic<I: IntoIterator<Item = T>>(&self, iter: I) {
+        self.enqueue_iter_indexed(iter);
+    }
+
+    /// Same as [`enqueue_atomic`](Queue::enqueue_atomic), returning the contiguous range of
+    /// indices assigned to the batch, e.g. to acknowledge it by offsets.
+    ///
+    /// Indices wrap at `usize::MAX`, in which case the returned range has its end below its
+    /// start, and looks empty; an empty batch returns an empty range with unspecified bounds.
+    pub fn enqueue_iter_indexed<I: IntoIterator<Item = T>>(&self, iter: I) -> Range<usize> {
+        let mut first: *mut Node<T> = std::ptr::null_mut();
+        let mut last: *mut Node<T> = std::ptr::null_mut();
+        let mut count: usize = 0;
+        for value in iter {
+            count += 1;
+            let node = unsafe { self.new_node(value).as_mut() };
+            match unsafe { last.as_mut() } {
+                Some(last) => node.prev = last,
+                None => first = node,
+            }
+            last = node;
+        }
+        let Some(first) = (unsafe { first.as_mut() }) else {
+            return 0..0;
+        };
+        let high_watermark = self.count_enqueued(count);
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            first.prev = head;
+            match self
+                .head
+                .compare_exchange_weak(head, last, Ordering::SeqCst, Ordering::Relaxed)
+            {
+                Ok(_) => break,
+                Err(h) => {
+                    head = h;
+                    self.count_cas_retry();
+                }
             }
         }
+        let first_index = self.link(first, head);
+        // The batch is indexed backward, and each node is linked to its predecessor only once
+        // indexed, the first node last. As the first node cannot be dequeued before it has a
+        // next node, the successors are unreachable by consumers while being indexed, and
+        // producers walking back from the last node stop at a live indexed node.
+        let mut node = last;
+        let mut index = first_index.wrapping_add(count - 1);
+        while !std::ptr::eq(node, first) {
+            let n = unsafe { &mut *node };
+            n.index.set(index);
+            unsafe { &*n.prev }.next.store(node, Ordering::Release);
+            node = n.prev;
+            index = index.wrapping_sub(1);
+        }
+        if let Some(f) = high_watermark {
+            f();
+        }
+        first_index..first_index.wrapping_add(count)
+    }
+
+    /// Enqueue the values of `iter` in atomic chunks of `chunk` items, yielding the thread
+    /// between chunks, so that a huge batch does not monopolize the queue head against other
+    /// producers.
+    ///
+    /// Chunks are published in order, each one as a contiguous run, but items of other producers
+    /// can be interleaved between chunks.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk` is zero.
+    pub fn enqueue_iter_chunked<I: IntoIterator<Item = T>>(&self, iter: I, chunk: usize) {
+        assert!(chunk > 0, "chunk must not be zero");
+        let mut iter = iter.into_iter().peekable();
+        loop {
+            self.enqueue_atomic(iter.by_ref().take(chunk));
+            if iter.peek().is_none() {
+                break;
+            }
+            std::thread::yield_now();
+        }
+    }
+
+    /// Enqueue a value with a given index, to rebuild a queue continuing a recorded sequence.
+    ///
+    /// Indices are assigned contiguously, so only the index of an item enqueued in an empty
+    /// queue can be chosen; the following ones must be its successors. It must not be called
+    /// concurrently with other operations; both preconditions are checked in debug builds.
+    pub fn enqueue_with_index(&self, value: T, index: usize) {
+        match unsafe { self.head.load(Ordering::Relaxed).as_ref() } {
+            None => self.index.store(index, Ordering::Relaxed),
+            Some(head) => debug_assert_eq!(
+                head.index.get().map(|i| i.wrapping_add(1)),
+                Some(index),
+                "replayed index must follow the previous one"
+            ),
+        }
+        self.enqueue(value);
+        debug_assert_eq!(
+            unsafe { &*self.head.load(Ordering::Relaxed) }.index.get(),
+            Some(index),
+            "concurrent operation during replay"
+        );
+    }
+
+    /// Enqueue the value only if the queue holds less than `max_len` items.
+    ///
+    /// This is a best-effort bound: concurrent producers may all pass the length check before
+    /// enqueuing, exceeding `max_len`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the queue was not created with [`with_len_counter`](Queue::with_len_counter).
+    pub fn enqueue_if_under(&self, value: T, max_len: usize) -> Result<(), T> {
+        if self.approx_len() >= max_len {
+            return Err(value);
+        }
+        self.enqueue(value);
+        Ok(())
+    }
+
+    /// Register a callback invoked by `enqueue` when the queue goes from empty to non-empty.
+    ///
+    /// The callback is edge-triggered: it runs once per transition, after the item is
+    /// published, in the enqueuing thread. As it runs after the transition, the item may
+    /// already have been dequeued, or the callbacks of concurrent transitions be reordered.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a callback is already registered.
+    pub fn on_nonempty(&self, f: impl Fn() + Send + Sync + 'static) {
+        if self.on_nonempty.set(Box::new(f)).is_err() {
+            panic!("on_nonempty callback already registered");
+        }
+    }
+
+    /// Register a callback invoked by `dequeue` when the last item leaves the queue.
+    ///
+    /// Same semantics as [`on_nonempty`](Queue::on_nonempty), in the dequeuing thread.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a callback is already registered.
+    pub fn on_empty(&self, f: impl Fn() + Send + Sync + 'static) {
+        if self.on_empty.set(Box::new(f)).is_err() {
+            panic!("on_empty callback already registered");
+        }
+    }
+
+    /// Register a callback invoked by the enqueue making [`approx_len`](Queue::approx_len)
+    /// reach `threshold`, e.g. to pause upstream producers.
+    ///
+    /// The callback is edge-triggered: the length counter is incremented once per enqueue,
+    /// or once per batch, so a single enqueue observes each upward crossing, and it fires
+    /// again only after the length went back below `threshold`. It runs in the enqueuing
+    /// thread, after the items are published; as the counter includes items being enqueued or
+    /// dequeued concurrently, the crossing can be transient.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a callback is already registered, or if the queue was not created with
+    /// [`with_len_counter`](Queue::with_len_counter).
+    pub fn set_high_watermark(&self, threshold: usize, f: impl Fn() + Send + Sync + 'static) {
+        self.len_counter();
+        if self.high_watermark.set((threshold, Box::new(f))).is_err() {
+            panic!("high watermark callback already registered");
+        }
+    }
+
+    fn link(&self, node: &mut Node<T>, head: *mut Node<T>) -> usize {
         if !head.is_null() {
             let mut prev = head;
             let mut offset = 1;
-            loop {
+            let index = loop {
                 match unsafe { &*prev }.index.get() {
-                    Some(i) => {
-                        node.index.set(i.wrapping_add(offset));
-                        break;
-                    }
+                    Some(i) => break i.wrapping_add(offset),
                     None => {
                         if unsafe { &*prev }.prev.is_null() {
                             let index = self.index.load(Ordering::Acquire);
-                            match unsafe { &*prev }.index.get() {
-                                Some(i) => node.index.set(i.wrapping_add(offset)),
-                                None => node.index.set(index.wrapping_add(offset)),
-                            }
-                            break;
+                            break match unsafe { &*prev }.index.get() {
+                                Some(i) => i.wrapping_add(offset),
+                                None => index.wrapping_add(offset),
+                            };
                         }
                         prev = unsafe { &*prev }.prev;
                         offset += 1;
                     }
                 }
-            }
+            };
+            #[cfg(feature = "metrics")]
+            self.enqueue_counters
+                .index_walk_steps
+                .fetch_add(offset as u64 - 1, Ordering::Relaxed);
+            node.index.set(index);
             unsafe { &*head }.next.store(node, Ordering::Release);
+            index
         } else {
-            node.index.set(self.index.load(Ordering::Relaxed));
+            let index = self.index.load(Ordering::Relaxed);
+            node.index.set(index);
             self.tail.store(node, Ordering::SeqCst);
+            #[cfg(all(unix, feature = "fd"))]
+            if let Some(readiness) = self.readiness.get() {
+                readiness.notify();
+            }
+            if let Some(on_nonempty) = self.on_nonempty.get() {
+                on_nonempty();
+            }
+            index
         }
     }
 
@@ -199,13 +1126,14 @@ impl<T> Queue<T> {
         next: *mut Node<T>,
         index: usize,
     ) -> T {
-        debug_assert!(unsafe { &*tail }.index.get().is_some());
+        debug_assert!(std::ptr::eq(node, tail));
+        debug_assert_eq!(node.index.get(), Some(index));
         while let Err(t) =
             self.tail
                 .compare_exchange_weak(tail, next, Ordering::SeqCst, Ordering::Relaxed)
         {
             let current_index = self.index.load(Ordering::Relaxed);
-            if index != current_index - 1
+            if index != current_index.wrapping_sub(1)
                 || (!t.is_null()
                     && unsafe { &*t }.prev.is_null()
                     && unsafe { &*t }.index.get() == Some(current_index))
@@ -215,40 +1143,67 @@ impl<T> Queue<T> {
             tail = t
         }
         let value = unsafe { node.value.assume_init_read() };
+        if let Some(counter) = &self.len {
+            counter.len.fetch_sub(1, Ordering::Relaxed);
+        }
+        #[cfg(feature = "metrics")]
+        self.enqueue_counters
+            .total_dequeued
+            .fetch_add(1, Ordering::Relaxed);
         node.index.unset();
         node.next.store(std::ptr::null_mut(), Ordering::Release);
         self.cache.put(node.into());
         value
     }
 
-    pub fn dequeue_spin(&self, spin: usize) -> Dequeue<T> {
+    fn node_state(
+        &self,
+        tail: *mut Node<T>,
+        spin: usize,
+        spins: &mut usize,
+    ) -> Option<(usize, *mut Node<T>, *mut Node<T>)> {
+        let node = unsafe { &*tail };
+        self.spin_strategy
+            .spin_until(spin, spins, || node.index.get().is_some());
+        let tail_index = node.index.get()?;
+        self.spin_strategy.spin_until(spin, spins, || {
+            !node.next.load(Ordering::Relaxed).is_null()
+                || tail == self.head.load(Ordering::Relaxed)
+        });
+        let head = self.head.load(Ordering::Relaxed);
+        let next = node.next.load(Ordering::Relaxed);
+        if next.is_null() && tail != head {
+            return None;
+        }
+        Some((tail_index, head, next))
+    }
+
+    /// Dequeue an item together with its index in the queue sequence.
+    pub fn dequeue_indexed_spin(&self, spin: usize) -> Dequeue<(usize, T)> {
+        self.dequeue_counting_spins(spin, &mut 0)
+    }
+
+    /// Same as [`dequeue_spin`](Queue::dequeue_spin), also reporting the spinning done.
+    pub fn dequeue_spin_report(&self, spin: usize) -> (Dequeue<T>, SpinReport) {
+        let mut spins = 0;
+        let res = without_index(self.dequeue_counting_spins(spin, &mut spins));
+        let report = SpinReport {
+            spins,
+            fast_path: spins == 0,
+        };
+        (res, report)
+    }
+
+    fn dequeue_counting_spins(&self, spin: usize, spins: &mut usize) -> Dequeue<(usize, T)> {
+        debug_assert!(spin <= MAX_REASONABLE_SPIN, "unreasonable spin {spin}");
         let mut index = self.index.load(Ordering::Relaxed);
         let mut tail = self.tail.load(Ordering::Relaxed);
         while !tail.is_null() {
             let node = unsafe { &mut *tail };
-            for _ in 0..spin {
-                if node.index.get().is_some() {
-                    break;
-                }
-                std::hint::spin_loop()
-            }
-            let tail_index = match node.index.get() {
-                Some(i) => i,
+            let (tail_index, head, mut next) = match self.node_state(tail, spin, spins) {
+                Some(state) => state,
                 None => return Dequeue::Inconsistent,
             };
-            for _ in 0..spin {
-                if !node.next.load(Ordering::Relaxed).is_null()
-                    || tail == self.head.load(Ordering::Relaxed)
-                {
-                    break;
-                }
-                std::hint::spin_loop()
-            }
-            let head = self.head.load(Ordering::Relaxed);
-            let mut next = node.next.load(Ordering::Relaxed);
-            if next.is_null() && tail != head {
-                return Dequeue::Inconsistent;
-            }
             let next_index = index.wrapping_add(1);
             if index == tail_index
                 && match self.index.compare_exchange(
@@ -275,14 +1230,24 @@ impl<T> Queue<T> {
                         )
                         .is_ok()
                     {
-                        return Dequeue::Data(self.set_tail(node, tail, next, index));
-                    } else {
-                        for _ in 0..spin {
-                            if !node.next.load(Ordering::Acquire).is_null() {
-                                break;
-                            }
-                            std::hint::spin_loop()
+                        let expired = self.is_expired(node);
+                        let value = self.set_tail(node, tail, next, index);
+                        #[cfg(all(unix, feature = "fd"))]
+                        if let Some(readiness) = self.readiness.get() {
+                            readiness.drain(|| self.is_empty());
+                        }
+                        if let Some(on_empty) = self.on_empty.get() {
+                            on_empty();
                         }
+                        if expired {
+                            drop(value);
+                            return Dequeue::Empty;
+                        }
+                        return Dequeue::Data((index, value));
+                    } else {
+                        self.spin_strategy.spin_until(spin, spins, || {
+                            !node.next.load(Ordering::Acquire).is_null()
+                        });
                         next = node.next.load(Ordering::Acquire);
                         if next.is_null()
                             && self
@@ -302,7 +1267,14 @@ impl<T> Queue<T> {
                     }
                 }
                 debug_assert!(!next.is_null());
-                return Dequeue::Data(self.set_tail(node, tail, next, index));
+                let expired = self.is_expired(node);
+                let value = self.set_tail(node, tail, next, index);
+                if !expired {
+                    return Dequeue::Data((index, value));
+                }
+                drop(value);
+                index = self.index.load(Ordering::Relaxed);
+                tail = self.tail.load(Ordering::Relaxed);
             } else {
                 tail = next;
             }
@@ -310,35 +1282,260 @@ impl<T> Queue<T> {
         Dequeue::Empty
     }
 
-    pub fn dequeue(&self) -> Dequeue<T> {
-        self.dequeue_spin(0)
+    pub fn dequeue_indexed(&self) -> Dequeue<(usize, T)> {
+        self.dequeue_indexed_spin(0)
     }
-}
 
-impl<T> Drop for Queue<T> {
-    fn drop(&mut self) {
-        while let Dequeue::Data(_) = self.dequeue() {}
+    pub fn dequeue_spin(&self, spin: usize) -> Dequeue<T> {
+        without_index(self.dequeue_indexed_spin(spin))
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::collections::HashSet;
-    use std::sync::{Arc, Mutex};
-    use std::time::{Duration, Instant};
+    /// Same as [`dequeue_spin`](Queue::dequeue_spin), returning `None` both when the queue is
+    /// empty and when it is still inconsistent after spinning, which is then indistinguishable.
+    pub fn dequeue_spin_or_none(&self, spin: usize) -> Option<T> {
+        self.dequeue_spin(spin).data()
+    }
 
-    use crate::queue::{Dequeue, Queue};
+    pub fn dequeue(&self) -> Dequeue<T> {
+        match &self.auto_spin {
+            Some(auto_spin) => {
+                let res = self.dequeue_spin(auto_spin.spin());
+                auto_spin.update(&res);
+                res
+            }
+            None => self.dequeue_spin(0),
+        }
+    }
 
-    #[test]
-    fn synchronous() {
-        let queue = Queue::new();
-        assert_eq!(queue.dequeue(), Dequeue::Empty);
-        queue.enqueue(0);
-        assert_eq!(queue.dequeue(), Dequeue::Data(0));
-        queue.enqueue(1);
-        assert_eq!(queue.dequeue(), Dequeue::Data(1));
-        queue.enqueue(2);
-        queue.enqueue(3);
+    /// Fold every available item into an accumulator, stopping on `Empty` or `Inconsistent`.
+    pub fn drain_fold<B>(&self, init: B, mut f: impl FnMut(B, T) -> B) -> B {
+        let mut acc = init;
+        while let Dequeue::Data(value) = self.dequeue() {
+            acc = f(acc, value);
+        }
+        acc
+    }
+
+    /// Move every available item to `yes` or `no` depending on `pred`, and return the count.
+    ///
+    /// Items are moved one by one, so concurrent operations on the queues can interleave.
+    pub fn partition_into(
+        &self,
+        mut pred: impl FnMut(&T) -> bool,
+        yes: &Queue<T>,
+        no: &Queue<T>,
+    ) -> usize {
+        let mut count = 0;
+        while let Dequeue::Data(value) = self.dequeue() {
+            if pred(&value) {
+                yes.enqueue(value);
+            } else {
+                no.enqueue(value);
+            }
+            count += 1;
+        }
+        count
+    }
+
+    /// Retry `dequeue` until data is returned or `budget` has elapsed.
+    ///
+    /// The clock is only read every few attempts, so the budget can be exceeded by the duration
+    /// of these attempts.
+    pub fn dequeue_spin_for(&self, budget: Duration) -> Dequeue<T> {
+        let start = Instant::now();
+        loop {
+            for i in 0..CLOCK_CHECK_INTERVAL {
+                if let res @ Dequeue::Data(_) = self.dequeue() {
+                    return res;
+                }
+                self.spin_strategy.hint(i)
+            }
+            if start.elapsed() >= budget {
+                return self.dequeue();
+            }
+        }
+    }
+
+    /// Retry `dequeue` until data is returned or `deadline` is reached.
+    ///
+    /// The clock is only read every few attempts, like in
+    /// [`dequeue_spin_for`](Queue::dequeue_spin_for); when the deadline has already passed, a
+    /// single attempt is made.
+    pub fn dequeue_spin_until(&self, deadline: Instant) -> Dequeue<T> {
+        loop {
+            if Instant::now() >= deadline {
+                return self.dequeue();
+            }
+            for i in 0..CLOCK_CHECK_INTERVAL {
+                if let res @ Dequeue::Data(_) = self.dequeue() {
+                    return res;
+                }
+                self.spin_strategy.hint(i)
+            }
+        }
+    }
+
+    /// Index of the next item to be dequeued.
+    pub(crate) fn dequeue_index(&self) -> usize {
+        self.index.load(Ordering::SeqCst)
+    }
+
+    /// Return whether `dequeue` would currently return [`Dequeue::Data`], without consuming.
+    ///
+    /// Contrary to an emptiness check, it returns `false` when the queue is inconsistent. The
+    /// result is racy by nature, as concurrent operations can change it right after.
+    pub fn has_ready_item(&self) -> bool {
+        let index = self.index.load(Ordering::Relaxed);
+        let mut tail = self.tail.load(Ordering::Relaxed);
+        while !tail.is_null() {
+            match self.node_state(tail, 0, &mut 0) {
+                Some((tail_index, _, _)) if tail_index == index => return true,
+                Some((_, _, next)) => tail = next,
+                None => return false,
+            }
+        }
+        false
+    }
+
+    /// Return whether `dequeue` would currently return [`Dequeue::Inconsistent`], i.e. a
+    /// producer is publishing the next item, without consuming.
+    ///
+    /// Like [`has_ready_item`](Queue::has_ready_item), the result is racy, and the publishing
+    /// usually completes right after.
+    pub fn is_publishing(&self) -> bool {
+        let index = self.index.load(Ordering::Relaxed);
+        let mut tail = self.tail.load(Ordering::Relaxed);
+        while !tail.is_null() {
+            match self.node_state(tail, 0, &mut 0) {
+                Some((tail_index, _, _)) if tail_index == index => return false,
+                Some((_, _, next)) => tail = next,
+                None => return true,
+            }
+        }
+        false
+    }
+
+    pub fn dequeue_into<C: Extend<T>>(&self, collection: &mut C, max: usize) -> usize {
+        let mut count = 0;
+        collection.extend(std::iter::from_fn(|| {
+            if count == max {
+                return None;
+            }
+            let value = self.dequeue().data()?;
+            count += 1;
+            Some(value)
+        }));
+        count
+    }
+
+    /// Same as [`dequeue_into`](Queue::dequeue_into), also returning
+    /// [`approx_len`](Queue::approx_len) read at the end of the drain, e.g. to decide whether to
+    /// drain again right away or to yield.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the queue was not created with [`with_len_counter`](Queue::with_len_counter).
+    pub fn dequeue_n_with_remaining(&self, buf: &mut Vec<T>, max: usize) -> (usize, usize) {
+        let drained = self.dequeue_into(buf, max);
+        (drained, self.approx_len())
+    }
+
+    /// Dequeue the front item, if available, and enqueue `value` at the back, returning the
+    /// displaced item; on an empty queue, it is a plain enqueue.
+    ///
+    /// The two operations are not atomic together: concurrent operations may run in between,
+    /// e.g. a consumer dequeuing the next item before `value` is enqueued, so it is only a
+    /// replacement when the queue holds a single pending item with no concurrent producer.
+    pub fn replace_front(&self, value: T) -> Option<T> {
+        let front = self.dequeue().data();
+        self.enqueue(value);
+        front
+    }
+
+    /// Move every available item into a new queue, leaving `self` empty.
+    ///
+    /// Nodes can still be referenced by concurrent consumers of `self`, so they cannot be
+    /// detached in one step; items are moved one by one, in order, into the new queue, which
+    /// has its own cache. Items enqueued concurrently may end up in either queue.
+    pub fn take_all(&self) -> Queue<T> {
+        let mut queue = Queue::new();
+        self.dequeue_into(&mut queue, usize::MAX);
+        queue
+    }
+
+    /// Dequeue every available item, and return them most recent first.
+    ///
+    /// Like for [`take_all`](Queue::take_all), nodes can still be referenced by concurrent
+    /// consumers, so items are not taken by walking the queue from its head, but dequeued in
+    /// order and then reversed. Items dequeued concurrently are missing, and the ones enqueued
+    /// concurrently may or may not be included.
+    pub fn drain_rev(&self) -> Vec<T> {
+        let mut items = Vec::new();
+        self.dequeue_into(&mut items, usize::MAX);
+        items.reverse();
+        items
+    }
+}
+
+impl<T> Extend<T> for Queue<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        iter.into_iter().for_each(|value| self.enqueue(value))
+    }
+}
+
+impl<T> Default for Queue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Remaining items are dropped first, or passed to the drop handler, in FIFO order, then the
+/// nodes are freed with the cache; cached nodes hold no initialized value, so freeing them runs
+/// no `T` destructor.
+///
+/// Items, or the drop handler, must not enqueue into the queue being dropped, which would
+/// require reaching it through a raw pointer while it is exclusively borrowed; it is checked
+/// in debug builds.
+impl<T> Drop for Queue<T> {
+    fn drop(&mut self) {
+        #[cfg(debug_assertions)]
+        self.dropping.store(true, Ordering::Relaxed);
+        let Some(handler) = self.drop_handler.take() else {
+            while let Dequeue::Data(_) = self.dequeue() {}
+            return;
+        };
+        let mut panic = None;
+        while let Dequeue::Data(value) = self.dequeue() {
+            if let Err(payload) = std::panic::catch_unwind(AssertUnwindSafe(|| handler(value))) {
+                panic.get_or_insert(payload);
+            }
+        }
+        if let Some(payload) = panic.filter(|_| !std::thread::panicking()) {
+            std::panic::resume_unwind(payload);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashSet, VecDeque};
+    use std::panic::AssertUnwindSafe;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use crate::queue::{Dequeue, Queue, SpinStrategy, MAX_REASONABLE_SPIN};
+
+    #[test]
+    fn synchronous() {
+        let queue = Queue::new();
+        assert_eq!(queue.dequeue(), Dequeue::Empty);
+        queue.enqueue(0);
+        assert_eq!(queue.dequeue(), Dequeue::Data(0));
+        queue.enqueue(1);
+        assert_eq!(queue.dequeue(), Dequeue::Data(1));
+        queue.enqueue(2);
+        queue.enqueue(3);
         assert_eq!(queue.dequeue(), Dequeue::Data(2));
         queue.enqueue(4);
         queue.enqueue(5);
@@ -348,6 +1545,817 @@ mod tests {
         assert_eq!(queue.dequeue(), Dequeue::Empty);
     }
 
+    #[test]
+    fn from_vec() {
+        let queue = Queue::from_vec(vec![0, 1, 2]);
+        assert!(!queue.is_empty());
+        queue.enqueue(3);
+        for i in 0..4 {
+            assert_eq!(queue.dequeue(), Dequeue::Data(i));
+        }
+        assert_eq!(queue.dequeue(), Dequeue::Empty);
+        assert_eq!(Queue::<usize>::from_vec(vec![]).dequeue(), Dequeue::Empty);
+    }
+
+    #[test]
+    fn spin_strategy() {
+        for strategy in [
+            SpinStrategy::Pause,
+            SpinStrategy::Yield,
+            SpinStrategy::PauseThenYield,
+            SpinStrategy::Noop,
+        ] {
+            let queue = Arc::new(Queue::with_spin_strategy(strategy));
+            let producer = {
+                let queue = queue.clone();
+                std::thread::spawn(move || (0..1000).for_each(|i| queue.enqueue(i)))
+            };
+            let mut values = Vec::new();
+            while values.len() < 1000 {
+                if let Dequeue::Data(value) = queue.dequeue_spin(100) {
+                    values.push(value);
+                }
+            }
+            producer.join().unwrap();
+            assert_eq!(values, (0..1000).collect::<Vec<_>>());
+        }
+    }
+
+    /// Run `f` while the tail item looks still being published, by unlinking it from its
+    /// successor, then link it back.
+    fn with_unlinked_tail<T, R>(queue: &Queue<T>, f: impl FnOnce() -> R) -> R {
+        let tail = unsafe { &*queue.tail.load(Ordering::Relaxed) };
+        let next = tail.next.swap(std::ptr::null_mut(), Ordering::Relaxed);
+        let res = f();
+        tail.next.store(next, Ordering::Relaxed);
+        res
+    }
+
+    #[test]
+    fn auto_spin() {
+        let queue = Queue::with_auto_spin();
+        let auto_spin = queue.auto_spin.as_ref().unwrap();
+        assert_eq!(queue.dequeue(), Dequeue::Empty);
+        assert_eq!(auto_spin.spin(), 0);
+        queue.enqueue(0);
+        queue.enqueue(1);
+        with_unlinked_tail(&queue, || {
+            for _ in 0..8 {
+                let spin = auto_spin.spin();
+                assert_eq!(queue.dequeue(), Dequeue::Inconsistent);
+                assert!(auto_spin.spin() > spin);
+            }
+        });
+        let spin = auto_spin.spin();
+        assert_eq!(queue.dequeue(), Dequeue::Data(0));
+        assert_eq!(queue.dequeue(), Dequeue::Data(1));
+        assert_eq!(auto_spin.spin(), spin);
+        assert_eq!(queue.dequeue(), Dequeue::Empty);
+        assert!(auto_spin.spin() < spin);
+    }
+
+    #[test]
+    fn enqueue_if_under() {
+        let queue = Arc::new(Queue::with_len_counter());
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let queue = queue.clone();
+                std::thread::spawn(move || {
+                    (0..100)
+                        .filter(|&i| queue.enqueue_if_under(i, 10).is_ok())
+                        .count()
+                })
+            })
+            .collect();
+        let enqueued: usize = threads.into_iter().map(|t| t.join().unwrap()).sum();
+        assert!((10..10 + 4).contains(&enqueued));
+        assert_eq!(queue.approx_len(), enqueued);
+        assert_eq!(queue.enqueue_if_under(0, 10), Err(0));
+        queue.dequeue_into(&mut Vec::new(), usize::MAX);
+        assert_eq!(queue.approx_len(), 0);
+        assert_eq!(queue.enqueue_if_under(0, 10), Ok(()));
+    }
+
+    #[test]
+    fn transition_callbacks() {
+        let queue = Queue::new();
+        let nonempty = Arc::new(AtomicUsize::new(0));
+        let empty = Arc::new(AtomicUsize::new(0));
+        let counter = nonempty.clone();
+        queue.on_nonempty(move || {
+            counter.fetch_add(1, Ordering::Relaxed);
+        });
+        let counter = empty.clone();
+        queue.on_empty(move || {
+            counter.fetch_add(1, Ordering::Relaxed);
+        });
+        let counts = || {
+            (
+                nonempty.load(Ordering::Relaxed),
+                empty.load(Ordering::Relaxed),
+            )
+        };
+        queue.enqueue(0);
+        queue.enqueue(1);
+        assert_eq!(counts(), (1, 0));
+        queue.dequeue();
+        assert_eq!(counts(), (1, 0));
+        queue.dequeue();
+        assert_eq!(counts(), (1, 1));
+        queue.dequeue();
+        queue.enqueue(2);
+        assert_eq!(counts(), (2, 1));
+    }
+
+    #[test]
+    fn high_watermark() {
+        let queue = Queue::with_len_counter();
+        let crossings = Arc::new(AtomicUsize::new(0));
+        let counter = crossings.clone();
+        queue.set_high_watermark(3, move || {
+            counter.fetch_add(1, Ordering::Relaxed);
+        });
+        queue.enqueue_atomic(0..2);
+        assert_eq!(crossings.load(Ordering::Relaxed), 0);
+        queue.enqueue_atomic(2..5);
+        assert_eq!(crossings.load(Ordering::Relaxed), 1);
+        queue.dequeue();
+        queue.enqueue(5);
+        assert_eq!(crossings.load(Ordering::Relaxed), 1);
+        for _ in 0..3 {
+            queue.dequeue();
+        }
+        queue.enqueue(6);
+        assert_eq!(crossings.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn high_watermark_after_publish() {
+        let queue = Arc::new(Queue::with_len_counter());
+        let dequeued = Arc::new(Mutex::new(Vec::new()));
+        let (weak, items) = (Arc::downgrade(&queue), dequeued.clone());
+        queue.set_high_watermark(1, move || {
+            let queue = weak.upgrade().unwrap();
+            items.lock().unwrap().push(queue.dequeue());
+        });
+        queue.enqueue(0);
+        queue.enqueue_atomic(1..3);
+        let expected = [Dequeue::Data(0), Dequeue::Data(1)];
+        assert_eq!(*dequeued.lock().unwrap(), expected);
+    }
+
+    #[test]
+    #[should_panic(expected = "queue created without length counter")]
+    fn approx_len_without_counter() {
+        Queue::<usize>::new().approx_len();
+    }
+
+    #[test]
+    #[cfg(feature = "metrics")]
+    fn enqueue_stats() {
+        let queue = Arc::new(Queue::new());
+        queue.enqueue_atomic(0..3);
+        let stats = queue.enqueue_stats();
+        assert_eq!((stats.enqueues, stats.cas_retries), (3, 0));
+        let start = Instant::now();
+        while queue.enqueue_stats().cas_retries == 0 {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            let threads: Vec<_> = (0..4)
+                .map(|_| {
+                    let queue = queue.clone();
+                    std::thread::spawn(move || (0..10_000).for_each(|i| queue.enqueue(i)))
+                })
+                .collect();
+            threads.into_iter().for_each(|t| t.join().unwrap());
+            while queue.dequeue().data().is_some() {}
+        }
+        queue.reset_enqueue_stats();
+        assert_eq!(queue.enqueue_stats(), Default::default());
+    }
+
+    #[test]
+    fn alloc_batch() {
+        for batch in [1, 8, 32] {
+            let queue = Queue::with_alloc_batch(batch);
+            for round in 0..3 {
+                (0..100).for_each(|i| queue.enqueue(round * 100 + i));
+                let values: Vec<_> = std::iter::from_fn(|| queue.dequeue().data()).collect();
+                assert_eq!(values, (round * 100..round * 100 + 100).collect::<Vec<_>>());
+            }
+            #[cfg(feature = "metrics")]
+            assert_eq!(queue.cache_stats().1, 100usize.div_ceil(batch) as u64);
+        }
+    }
+
+    #[test]
+    fn prefault() {
+        let queue = Queue::with_capacity(4);
+        assert_eq!(queue.health().cached_nodes, 4);
+        queue.prefault(8);
+        queue.prefault(2);
+        assert_eq!(queue.health().cached_nodes, 8);
+        for round in 0..2 {
+            (0..10).for_each(|i| queue.enqueue([round, i]));
+            let values: Vec<_> = std::iter::from_fn(|| queue.dequeue().data()).collect();
+            assert_eq!(values, (0..10).map(|i| [round, i]).collect::<Vec<_>>());
+        }
+        #[cfg(feature = "metrics")]
+        assert_eq!(queue.cache_stats(), (18, 2));
+    }
+
+    #[test]
+    #[cfg(feature = "metrics")]
+    fn total_counts() {
+        let mut queue = Queue::from_vec(vec![0, 1]);
+        queue.enqueue_atomic(2..5);
+        queue.enqueue(5);
+        queue.dequeue();
+        queue.dequeue_into(&mut Vec::new(), 2);
+        assert_eq!((queue.total_enqueued(), queue.total_dequeued()), (6, 3));
+        queue.defragment();
+        assert_eq!((queue.total_enqueued(), queue.total_dequeued()), (6, 3));
+        let diff = queue.total_enqueued() - queue.total_dequeued();
+        assert_eq!(diff as usize, queue.drain_fold(0, |n, _| n + 1));
+        queue.reset_enqueue_stats();
+        assert_eq!(queue.total_enqueued(), 6);
+        queue.enqueue(6);
+        assert_eq!(queue.enqueue_stats().enqueues, 1);
+    }
+
+    #[test]
+    #[cfg(feature = "metrics")]
+    fn cache_stats() {
+        let queue = Queue::new();
+        queue.enqueue(0);
+        queue.enqueue(1);
+        assert_eq!(queue.cache_stats(), (0, 2));
+        queue.dequeue_into(&mut Vec::new(), usize::MAX);
+        queue.enqueue(2);
+        queue.enqueue(3);
+        queue.enqueue(4);
+        assert_eq!(queue.cache_stats(), (2, 3));
+        queue.reset_cache_stats();
+        assert_eq!(queue.cache_stats(), (0, 0));
+    }
+
+    #[test]
+    fn enqueue_stamped() {
+        let (queue1, queue2) = (Queue::new(), Queue::new());
+        assert_ne!(queue1.id(), queue2.id());
+        let stamps1: Vec<_> = (0..3).map(|i| queue1.enqueue_stamped(i)).collect();
+        queue2.enqueue(0);
+        let stamps2: Vec<_> = (0..3).map(|i| queue2.enqueue_stamped(i)).collect();
+        assert!(stamps1.iter().all(|s| s.queue_id == queue1.id()));
+        assert!(stamps2.iter().all(|s| s.queue_id == queue2.id()));
+        assert_eq!(
+            stamps1.iter().map(|s| s.index).collect::<Vec<_>>(),
+            [0, 1, 2]
+        );
+        assert_eq!(
+            stamps2.iter().map(|s| s.index).collect::<Vec<_>>(),
+            [1, 2, 3]
+        );
+        queue2.dequeue();
+        assert_eq!(
+            queue2.dequeue_indexed(),
+            Dequeue::Data((stamps2[0].index, 0))
+        );
+    }
+
+    #[test]
+    fn enqueue_iter_indexed() {
+        let queue = Queue::new();
+        queue.enqueue('a');
+        assert_eq!(queue.enqueue_iter_indexed(['b', 'c', 'd']), 1..4);
+        assert!(queue.enqueue_iter_indexed([]).is_empty());
+        assert_eq!(queue.enqueue_iter_indexed(['e']), 4..5);
+        let indices: Vec<_> = std::iter::from_fn(|| queue.dequeue_indexed().data())
+            .map(|(index, _)| index)
+            .collect();
+        assert_eq!(indices, (0..5).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn enqueue_iter_indexed_interleaved() {
+        // on_nonempty runs after the first node of the batch is indexed, and before the others
+        let queue = Arc::new(Queue::new());
+        let interleaved = Arc::new(Mutex::new(None));
+        queue.on_nonempty({
+            let (queue, interleaved) = (Arc::downgrade(&queue), interleaved.clone());
+            move || {
+                let queue = queue.upgrade().unwrap();
+                let mut interleaved = interleaved.lock().unwrap();
+                if interleaved.is_none() {
+                    *interleaved = Some(queue.dequeue_indexed());
+                    queue.enqueue(4);
+                }
+            }
+        });
+        assert_eq!(queue.enqueue_iter_indexed([1, 2, 3]), 0..3);
+        assert_eq!(*interleaved.lock().unwrap(), Some(Dequeue::Inconsistent));
+        let items: Vec<_> = std::iter::from_fn(|| queue.dequeue_indexed().data()).collect();
+        assert_eq!(items, [(0, 1), (1, 2), (2, 3), (3, 4)]);
+    }
+
+    #[test]
+    fn enqueue_with_index() {
+        let queue = Queue::new();
+        for (index, value) in [(42, 'a'), (43, 'b'), (44, 'c')] {
+            queue.enqueue_with_index(value, index);
+        }
+        assert_eq!(queue.dequeue_indexed(), Dequeue::Data((42, 'a')));
+        let resumed = Queue::new();
+        while let Dequeue::Data((index, value)) = queue.dequeue_indexed() {
+            resumed.enqueue_with_index(value, index);
+        }
+        resumed.enqueue('d');
+        assert_eq!(resumed.dequeue_indexed(), Dequeue::Data((43, 'b')));
+        assert_eq!(resumed.dequeue_indexed(), Dequeue::Data((44, 'c')));
+        assert_eq!(resumed.dequeue_indexed(), Dequeue::Data((45, 'd')));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "replayed index")]
+    fn enqueue_with_index_gap() {
+        let queue = Queue::new();
+        queue.enqueue_with_index(0, 0);
+        queue.enqueue_with_index(2, 2);
+    }
+
+    #[test]
+    #[cfg(feature = "timestamps")]
+    fn oldest_age() {
+        let queue = Queue::new();
+        assert_eq!(queue.oldest_age(), None);
+        queue.enqueue(0);
+        std::thread::sleep(Duration::from_millis(20));
+        queue.enqueue(1);
+        assert!(queue.oldest_age().unwrap() >= Duration::from_millis(20));
+        queue.dequeue();
+        assert!(queue.oldest_age().unwrap() < Duration::from_millis(20));
+        queue.dequeue();
+        assert_eq!(queue.oldest_age(), None);
+    }
+
+    #[test]
+    #[cfg(feature = "timestamps")]
+    fn ttl() {
+        let queue = Queue::with_ttl(Duration::from_millis(20));
+        queue.enqueue(0);
+        queue.enqueue(1);
+        std::thread::sleep(Duration::from_millis(30));
+        queue.enqueue(2);
+        assert_eq!(queue.dequeue(), Dequeue::Data(2));
+        queue.enqueue(3);
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(queue.dequeue(), Dequeue::Empty);
+        assert!(queue.is_empty());
+        queue.enqueue(4);
+        assert_eq!(queue.dequeue(), Dequeue::Data(4));
+    }
+
+    #[test]
+    fn max_depth() {
+        let queue = Queue::with_len_counter();
+        (0..5).for_each(|i| queue.enqueue(i));
+        queue.dequeue_into(&mut Vec::new(), 3);
+        queue.enqueue(5);
+        assert_eq!(queue.max_depth(), 5);
+        queue.reset_max_depth();
+        assert_eq!(queue.max_depth(), 3);
+        queue.enqueue(6);
+        assert_eq!(queue.max_depth(), 4);
+    }
+
+    #[test]
+    fn defragment() {
+        let mut queue = Queue::new();
+        let empty = Arc::new(AtomicUsize::new(0));
+        let counter = empty.clone();
+        queue.on_empty(move || {
+            counter.fetch_add(1, Ordering::Relaxed);
+        });
+        for i in 0..10 {
+            queue.enqueue(i);
+            if i % 3 == 0 {
+                queue.dequeue();
+            }
+        }
+        queue.defragment();
+        assert_eq!(empty.load(Ordering::Relaxed), 1);
+        let mut node = *queue.tail.get_mut();
+        for _ in 4..9 {
+            let next = unsafe { &*node }.next.load(Ordering::Relaxed);
+            assert_eq!(next, node.wrapping_add(1));
+            node = next;
+        }
+        assert_eq!(queue.health().cached_nodes, 0);
+        queue.enqueue(10);
+        let mut values = Vec::new();
+        while let Dequeue::Data(value) = queue.dequeue_indexed() {
+            values.push(value);
+        }
+        assert_eq!(values, [4, 5, 6, 7, 8, 9, 10].map(|i| (i, i)));
+        assert_eq!(empty.load(Ordering::Relaxed), 2);
+        // frees the previous block, whose nodes are all cached
+        queue.defragment();
+        assert_eq!(queue.health().cached_nodes, 0);
+    }
+
+    #[test]
+    fn drain_rev() {
+        let queue = Queue::new();
+        assert!(queue.drain_rev().is_empty());
+        (0..10).for_each(|i| queue.enqueue(i));
+        assert_eq!(queue.drain_rev(), (0..10).rev().collect::<Vec<_>>());
+        assert_eq!(queue.dequeue(), Dequeue::Empty);
+    }
+
+    #[test]
+    fn dequeue_n_with_remaining() {
+        let queue = Queue::with_len_counter();
+        (0..10).for_each(|i| queue.enqueue(i));
+        let mut buf = Vec::new();
+        assert_eq!(queue.dequeue_n_with_remaining(&mut buf, 3), (3, 7));
+        assert_eq!(buf, [0, 1, 2]);
+        assert_eq!(queue.dequeue_n_with_remaining(&mut buf, 10), (7, 0));
+        assert_eq!(buf, (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn dequeue_into() {
+        let queue = Queue::new();
+        for i in 0..5 {
+            queue.enqueue(i);
+        }
+        let mut deque = VecDeque::new();
+        assert_eq!(queue.dequeue_into(&mut deque, 3), 3);
+        assert_eq!(deque, [0, 1, 2]);
+        assert_eq!(queue.dequeue_into(&mut deque, 3), 2);
+        assert_eq!(deque, [0, 1, 2, 3, 4]);
+        let queue = Queue::new();
+        "hello".chars().for_each(|c| queue.enqueue(c));
+        let mut string = String::new();
+        assert_eq!(queue.dequeue_into(&mut string, usize::MAX), 5);
+        assert_eq!(string, "hello");
+        assert_eq!(queue.dequeue(), Dequeue::Empty);
+    }
+
+    #[test]
+    fn drop_after_shared_use() {
+        let value = Arc::new(());
+        let queue = Arc::new(Queue::new());
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let queue = queue.clone();
+                let value = value.clone();
+                std::thread::spawn(move || queue.enqueue(value))
+            })
+            .collect();
+        drop(queue);
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn take_all() {
+        let queue = Queue::new();
+        queue.enqueue(0);
+        queue.enqueue(1);
+        let taken = queue.take_all();
+        assert_eq!(queue.dequeue(), Dequeue::Empty);
+        queue.enqueue(2);
+        assert_eq!(taken.dequeue(), Dequeue::Data(0));
+        assert_eq!(taken.dequeue(), Dequeue::Data(1));
+        assert_eq!(taken.dequeue(), Dequeue::Empty);
+        assert_eq!(queue.dequeue(), Dequeue::Data(2));
+    }
+
+    #[test]
+    fn dequeue_spin_report() {
+        let queue = Queue::new();
+        let (res, report) = queue.dequeue_spin_report(10);
+        assert_eq!(res, Dequeue::Empty);
+        assert!(report.fast_path);
+        queue.enqueue(0);
+        queue.enqueue(1);
+        let (res, report) = with_unlinked_tail(&queue, || queue.dequeue_spin_report(10));
+        assert_eq!(res, Dequeue::Inconsistent);
+        assert_eq!((report.spins, report.fast_path), (10, false));
+        let (res, report) = queue.dequeue_spin_report(10);
+        assert_eq!(res, Dequeue::Data(0));
+        assert!(report.fast_path);
+    }
+
+    #[test]
+    fn dequeue_spin_or_none() {
+        let queue = Queue::new();
+        assert_eq!(queue.dequeue_spin_or_none(10), None);
+        queue.enqueue(0);
+        queue.enqueue(1);
+        let res = with_unlinked_tail(&queue, || queue.dequeue_spin_or_none(10));
+        assert_eq!(res, None);
+        assert_eq!(queue.dequeue_spin_or_none(10), Some(0));
+        assert_eq!(queue.dequeue_spin_or_none(0), Some(1));
+    }
+
+    #[test]
+    fn is_publishing() {
+        let queue = Queue::new();
+        assert!(!queue.is_publishing());
+        queue.enqueue(0);
+        queue.enqueue(1);
+        assert!(!queue.is_publishing());
+        with_unlinked_tail(&queue, || {
+            assert!(queue.is_publishing());
+            assert_eq!(queue.dequeue(), Dequeue::Inconsistent);
+        });
+        assert!(!queue.is_publishing());
+        assert_eq!(queue.dequeue(), Dequeue::Data(0));
+        let head = unsafe { &mut *queue.head.load(Ordering::Relaxed) };
+        head.index.unset();
+        assert!(queue.is_publishing());
+        head.index.set(1);
+        assert_eq!(queue.dequeue(), Dequeue::Data(1));
+        assert!(!queue.is_publishing());
+    }
+
+    #[test]
+    fn has_ready_item() {
+        let queue = Queue::new();
+        assert!(!queue.has_ready_item());
+        queue.enqueue(0);
+        queue.enqueue(1);
+        assert!(queue.has_ready_item());
+        with_unlinked_tail(&queue, || {
+            assert!(!queue.has_ready_item());
+            assert_eq!(queue.dequeue(), Dequeue::Inconsistent);
+        });
+        assert!(queue.has_ready_item());
+        assert_eq!(queue.dequeue(), Dequeue::Data(0));
+        assert_eq!(queue.dequeue(), Dequeue::Data(1));
+        assert!(!queue.has_ready_item());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "unreasonable spin")]
+    fn unreasonable_spin() {
+        Queue::<usize>::new().dequeue_spin(MAX_REASONABLE_SPIN + 1);
+    }
+
+    #[test]
+    fn replace_front() {
+        let queue = Queue::new();
+        assert_eq!(queue.replace_front("a"), None);
+        assert_eq!(queue.replace_front("b"), Some("a"));
+        assert_eq!(queue.dequeue(), Dequeue::Data("b"));
+        assert_eq!(queue.dequeue(), Dequeue::Empty);
+    }
+
+    #[test]
+    fn drain_fold() {
+        let queue = Queue::from_vec((1..=10u64).collect());
+        assert_eq!(queue.drain_fold(0, |sum, i| sum + i), 55);
+        assert_eq!(queue.drain_fold(0, |sum, i| sum + i), 0);
+        queue.enqueue(1);
+        assert_eq!(queue.drain_fold(0, |sum, i| sum + i), 1);
+    }
+
+    #[test]
+    fn partition_into() {
+        let queue = Queue::from_vec((0..6).collect());
+        let (even, odd) = (Queue::new(), Queue::new());
+        assert_eq!(queue.partition_into(|i| i % 2 == 0, &even, &odd), 6);
+        assert_eq!(queue.dequeue(), Dequeue::Empty);
+        let mut evens = Vec::new();
+        even.dequeue_into(&mut evens, usize::MAX);
+        assert_eq!(evens, [0, 2, 4]);
+        let mut odds = Vec::new();
+        odd.dequeue_into(&mut odds, usize::MAX);
+        assert_eq!(odds, [1, 3, 5]);
+    }
+
+    #[test]
+    fn dequeue_spin_for() {
+        let queue = Queue::new();
+        let start = Instant::now();
+        assert_eq!(
+            queue.dequeue_spin_for(Duration::from_millis(1)),
+            Dequeue::Empty
+        );
+        assert!(start.elapsed() >= Duration::from_millis(1));
+        queue.enqueue(0);
+        assert_eq!(
+            queue.dequeue_spin_for(Duration::from_secs(10)),
+            Dequeue::Data(0)
+        );
+    }
+
+    extern "C" fn enqueue_shim(queue: *const Queue<usize>, value: usize) {
+        unsafe { &*queue }.enqueue(value)
+    }
+
+    extern "C" fn dequeue_shim(queue: *const Queue<usize>, value: *mut usize) -> bool {
+        match unsafe { &*queue }.dequeue() {
+            Dequeue::Data(v) => {
+                unsafe { value.write(v) };
+                true
+            }
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn ffi_pointer() {
+        let queue = Box::pin(Queue::new());
+        let ptr: *const Queue<usize> = &*queue;
+        let threads: Vec<_> = (0..4)
+            .map(|i| {
+                let ptr = ptr as usize;
+                std::thread::spawn(move || enqueue_shim(ptr as *const _, i))
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        let mut values = HashSet::new();
+        let mut value = 0;
+        while dequeue_shim(ptr, &mut value) {
+            values.insert(value);
+        }
+        assert_eq!(values, (0..4).collect());
+        drop(queue);
+    }
+
+    #[test]
+    fn cache_shards() {
+        let queue = Arc::new(Queue::with_cache_shards(4));
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let queue = queue.clone();
+                std::thread::spawn(move || {
+                    for i in 0..1000 {
+                        queue.enqueue(i);
+                        while queue.dequeue().data().is_none() {}
+                    }
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert!(queue.is_empty());
+        assert_eq!(queue.dequeue(), Dequeue::Empty);
+    }
+
+    #[test]
+    fn enqueue_atomic() {
+        let queue = Arc::new(Queue::new());
+        let threads: Vec<_> = (0..4)
+            .map(|producer| {
+                let queue = queue.clone();
+                std::thread::spawn(move || {
+                    for batch in 0..100 {
+                        queue.enqueue_atomic((0..10).map(|i| (producer, batch, i)));
+                    }
+                })
+            })
+            .collect();
+        let mut values = Vec::new();
+        while values.len() < 4 * 100 * 10 {
+            if let Dequeue::Data(value) = queue.dequeue() {
+                values.push(value);
+            }
+        }
+        for t in threads {
+            t.join().unwrap();
+        }
+        for chunk in values.chunks(10) {
+            let (producer, batch, _) = chunk[0];
+            assert_eq!(
+                chunk,
+                (0..10).map(|i| (producer, batch, i)).collect::<Vec<_>>()
+            );
+        }
+        queue.enqueue_atomic([]);
+        assert_eq!(queue.dequeue(), Dequeue::Empty);
+    }
+
+    #[test]
+    fn enqueue_atomic_mixed() {
+        let queue = Arc::new(Queue::new());
+        let threads: Vec<_> = (0..4)
+            .map(|producer| {
+                let queue = queue.clone();
+                std::thread::spawn(move || {
+                    for batch in 0..500 {
+                        let values = (0..5).map(move |i| (producer, batch * 5 + i));
+                        if producer % 2 == 0 {
+                            queue.enqueue_atomic(values);
+                        } else {
+                            values.for_each(|value| queue.enqueue(value));
+                        }
+                        std::thread::yield_now();
+                    }
+                })
+            })
+            .collect();
+        let mut next = [0; 4];
+        let mut expected_index = 0;
+        while expected_index < 4 * 500 * 5 {
+            if let Dequeue::Data((index, (producer, i))) = queue.dequeue_indexed() {
+                assert_eq!(index, expected_index);
+                assert_eq!(i, next[producer]);
+                next[producer] += 1;
+                expected_index += 1;
+            }
+        }
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert_eq!(queue.dequeue(), Dequeue::Empty);
+    }
+
+    #[test]
+    fn enqueue_iter_chunked() {
+        let queue = Arc::new(Queue::new());
+        let threads: Vec<_> = (0..4)
+            .map(|producer| {
+                let queue = queue.clone();
+                std::thread::spawn(move || {
+                    queue.enqueue_iter_chunked((0..1000).map(|i| (producer, i)), 10)
+                })
+            })
+            .collect();
+        let mut values = Vec::new();
+        while values.len() < 4 * 1000 {
+            if let Dequeue::Data(value) = queue.dequeue() {
+                values.push(value);
+            }
+        }
+        for t in threads {
+            t.join().unwrap();
+        }
+        for chunk in values.chunks(10) {
+            let (producer, first) = chunk[0];
+            assert_eq!(first % 10, 0);
+            assert_eq!(
+                chunk,
+                (first..first + 10)
+                    .map(|i| (producer, i))
+                    .collect::<Vec<_>>()
+            );
+        }
+        for producer in 0..4 {
+            let items = values.iter().filter(|(p, _)| *p == producer);
+            assert!(items.map(|(_, i)| *i).eq(0..1000));
+        }
+    }
+
+    struct DropLogger(usize, Arc<Mutex<Vec<usize>>>);
+
+    impl Drop for DropLogger {
+        fn drop(&mut self) {
+            self.1.lock().unwrap().push(self.0);
+        }
+    }
+
+    #[test]
+    fn drop_order() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let queue = Queue::new();
+        for i in 0..4 {
+            queue.enqueue(DropLogger(i, log.clone()));
+        }
+        drop(queue.dequeue());
+        assert_eq!(*log.lock().unwrap(), [0]);
+        drop(queue);
+        assert_eq!(*log.lock().unwrap(), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn drop_handler() {
+        let handled = Arc::new(Mutex::new(Vec::new()));
+        let queue = {
+            let handled = handled.clone();
+            Queue::with_drop_handler(move |i| {
+                handled.lock().unwrap().push(i);
+                assert_ne!(i, 1);
+            })
+        };
+        for i in 0..4 {
+            queue.enqueue(i);
+        }
+        queue.dequeue();
+        let res = std::panic::catch_unwind(AssertUnwindSafe(|| drop(queue)));
+        assert!(res.is_err());
+        assert_eq!(*handled.lock().unwrap(), [1, 2, 3]);
+    }
+
     fn test_asynchronous(nb_values: usize) {
         let start = Instant::now();
         let queue = Arc::new(Queue::new());
@@ -0,0 +1,70 @@
+use crate::Queue;
+
+/// Reaction of a [`ConsumeScope`] finding items left in the queue when dropped.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub enum ConsumeMode {
+    /// Panic, unless the thread is already panicking.
+    Panic,
+    /// Block, yielding the thread, until other consumers have emptied the queue.
+    Block,
+    /// Do nothing.
+    Ignore,
+}
+
+/// Guard checking, when dropped, that all the items of a queue have been consumed.
+pub struct ConsumeScope<'a, T> {
+    queue: &'a Queue<T>,
+    mode: ConsumeMode,
+}
+
+impl<T> Queue<T> {
+    pub fn consume_scope(&self, mode: ConsumeMode) -> ConsumeScope<'_, T> {
+        ConsumeScope { queue: self, mode }
+    }
+}
+
+impl<T> Drop for ConsumeScope<'_, T> {
+    fn drop(&mut self) {
+        match self.mode {
+            ConsumeMode::Panic if !self.queue.is_empty() && !std::thread::panicking() => {
+                panic!("items left unconsumed")
+            }
+            ConsumeMode::Block => {
+                while !self.queue.is_empty() {
+                    std::thread::yield_now()
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::AssertUnwindSafe;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::{ConsumeMode, Dequeue, Queue};
+
+    #[test]
+    fn consume_scope() {
+        let queue = Arc::new(Queue::new());
+        queue.enqueue(0);
+        drop(queue.consume_scope(ConsumeMode::Ignore));
+        let res = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            drop(queue.consume_scope(ConsumeMode::Panic))
+        }));
+        assert!(res.is_err());
+        {
+            let _scope = queue.consume_scope(ConsumeMode::Block);
+            let queue = queue.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(1));
+                queue.dequeue()
+            });
+        }
+        assert_eq!(queue.dequeue(), Dequeue::Empty);
+        drop(queue.consume_scope(ConsumeMode::Panic));
+    }
+}
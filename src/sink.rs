@@ -0,0 +1,69 @@
+use crate::{Queue, SynchronizedQueue};
+
+/// Borrowed queue usable as an [`Extend`] sink in iterator chains, each item being enqueued
+/// separately; a [`SynchronizedQueue`] sink notifies a waiter for each item.
+pub struct QueueSink<'a, Q> {
+    queue: &'a Q,
+}
+
+impl<T> Queue<T> {
+    pub fn as_sink(&self) -> QueueSink<'_, Self> {
+        QueueSink { queue: self }
+    }
+}
+
+impl<T> SynchronizedQueue<T> {
+    pub fn as_sink(&self) -> QueueSink<'_, Self> {
+        QueueSink { queue: self }
+    }
+}
+
+impl<T> QueueSink<'_, Queue<T>> {
+    pub fn push(&mut self, value: T) {
+        self.queue.enqueue(value)
+    }
+}
+
+impl<T> QueueSink<'_, SynchronizedQueue<T>> {
+    pub fn push(&mut self, value: T) {
+        self.queue.enqueue(value)
+    }
+}
+
+impl<T> Extend<T> for QueueSink<'_, Queue<T>> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        iter.into_iter().for_each(|value| self.push(value))
+    }
+}
+
+impl<T> Extend<T> for QueueSink<'_, SynchronizedQueue<T>> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        iter.into_iter().for_each(|value| self.push(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{Dequeue, Queue, SynchronizedQueue};
+
+    #[test]
+    fn sink() {
+        let queue = Queue::new();
+        let mut sink = queue.as_sink();
+        sink.extend(0..50);
+        (50..100).for_each(|i| sink.push(i));
+        let values: Vec<_> = std::iter::from_fn(|| queue.dequeue().data()).collect();
+        assert_eq!(values, (0..100).collect::<Vec<_>>());
+
+        let queue = Arc::new(SynchronizedQueue::new());
+        let consumer = {
+            let queue = queue.clone();
+            std::thread::spawn(move || (0..10).map(|_| queue.dequeue()).collect::<Vec<_>>())
+        };
+        queue.as_sink().extend(0..10);
+        assert_eq!(consumer.join().unwrap(), (0..10).collect::<Vec<_>>());
+        assert_eq!(queue.try_dequeue(), Dequeue::Empty);
+    }
+}
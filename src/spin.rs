@@ -0,0 +1,77 @@
+use crate::{Dequeue, Queue};
+
+const BACKOFF_MAX: usize = 1 << 6;
+
+/// Queue whose blocking dequeue only spins and never parks the thread.
+///
+/// It has no waker queue and makes no syscall, which suits real-time threads pinned to
+/// dedicated cores; on the other hand, a waiting consumer keeps its core fully busy.
+pub struct SpinQueue<T> {
+    inner: Queue<T>,
+}
+
+impl<T> SpinQueue<T> {
+    pub fn new() -> Self {
+        SpinQueue {
+            inner: Queue::new(),
+        }
+    }
+
+    pub fn enqueue(&self, value: T) {
+        self.inner.enqueue(value)
+    }
+
+    pub fn try_dequeue_spin(&self, spin: usize) -> Dequeue<T> {
+        self.inner.dequeue_spin(spin)
+    }
+
+    pub fn try_dequeue(&self) -> Dequeue<T> {
+        self.try_dequeue_spin(0)
+    }
+
+    /// Spin until an item is dequeued, with an exponential backoff between tries.
+    pub fn dequeue(&self) -> T {
+        let mut backoff = 1;
+        loop {
+            if let Dequeue::Data(value) = self.try_dequeue() {
+                return value;
+            }
+            for _ in 0..backoff {
+                std::hint::spin_loop()
+            }
+            backoff = (backoff * 2).min(BACKOFF_MAX);
+        }
+    }
+}
+
+impl<T> Default for SpinQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::spin::SpinQueue;
+
+    #[test]
+    fn spin_dequeue() {
+        let queue = Arc::new(SpinQueue::new());
+        {
+            let queue = queue.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(1));
+                for i in 0..3 {
+                    queue.enqueue(i)
+                }
+            });
+        }
+        assert_eq!(
+            [queue.dequeue(), queue.dequeue(), queue.dequeue()],
+            [0, 1, 2]
+        );
+    }
+}
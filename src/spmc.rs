@@ -0,0 +1,93 @@
+use std::sync::Arc;
+
+use crate::{Dequeue, Queue};
+
+/// Exclusive producer handle of a single-producer queue.
+///
+/// As there is no other producer competing, queue's *head* is swapped instead of going
+/// through the compare-and-swap loop. Exclusivity is enforced by the handle being neither
+/// `Clone` nor usable through a shared reference (`enqueue` takes `&mut self`).
+pub struct Producer<T> {
+    queue: Arc<Queue<T>>,
+}
+
+impl<T> Producer<T> {
+    pub fn enqueue(&mut self, value: T) {
+        self.queue.enqueue_exclusive(value)
+    }
+}
+
+/// Consumer handle of a single-producer queue; consumption stays multiple consumer.
+pub struct Consumer<T> {
+    queue: Arc<Queue<T>>,
+}
+
+impl<T> Consumer<T> {
+    pub fn dequeue_spin(&self, spin: usize) -> Dequeue<T> {
+        self.queue.dequeue_spin(spin)
+    }
+
+    pub fn dequeue(&self) -> Dequeue<T> {
+        self.queue.dequeue()
+    }
+}
+
+impl<T> Clone for Consumer<T> {
+    fn clone(&self) -> Self {
+        Consumer {
+            queue: self.queue.clone(),
+        }
+    }
+}
+
+impl<T> Queue<T> {
+    /// Split the queue into a unique [`Producer`] and a cloneable [`Consumer`].
+    pub fn into_single_producer(self) -> (Producer<T>, Consumer<T>) {
+        let queue = Arc::new(self);
+        (
+            Producer {
+                queue: queue.clone(),
+            },
+            Consumer { queue },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::sync::{Arc, Mutex};
+
+    use crate::{Dequeue, Queue};
+
+    #[test]
+    fn single_producer() {
+        let (mut producer, consumer) = Queue::new().into_single_producer();
+        let nb_values = 1000;
+        let values = Arc::new(Mutex::new(Vec::new()));
+        let mut threads = vec![];
+        for _ in 0..4 {
+            let consumer = consumer.clone();
+            let values = values.clone();
+            threads.push(std::thread::spawn(move || loop {
+                match consumer.dequeue_spin(100) {
+                    Dequeue::Data(Some(n)) => values.lock().unwrap().push(n),
+                    Dequeue::Data(None) => break,
+                    _ => std::hint::spin_loop(),
+                }
+            }));
+        }
+        for i in 0..nb_values {
+            producer.enqueue(Some(i));
+        }
+        for _ in 0..4 {
+            producer.enqueue(None);
+        }
+        for t in threads {
+            t.join().unwrap();
+        }
+        let values = values.lock().unwrap();
+        assert_eq!(values.len(), nb_values);
+        assert_eq!(values.iter().collect::<HashSet<_>>().len(), nb_values);
+    }
+}
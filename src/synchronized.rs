@@ -1,44 +1,77 @@
-use std::future::Future;
-use std::pin::Pin;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::future::{poll_fn, Future};
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::{pin, Pin};
+use std::sync::atomic::{self, AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::task::{Context, Poll, Waker as TaskWaker};
 use std::thread::Thread;
 use std::time::{Duration, Instant};
 
-use crate::{Dequeue, Queue};
+use crate::{Dequeue, Queue, QueueHealth};
+
+/// Runtime timer used by async timeouts, e.g. an adapter around the runtime's `sleep`.
+pub trait Timer {
+    fn sleep(&self, duration: Duration) -> impl Future<Output = ()>;
+}
+
+/// Source of the current time for timeouts, which can be mocked in tests.
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
 
 enum InnerWaker {
     Sync(Thread),
     Async(TaskWaker),
 }
 
-struct Waker {
+pub(crate) struct Waker {
     inner: InnerWaker,
     notified: AtomicBool,
 }
 
 impl Waker {
-    fn new_sync() -> Self {
+    pub(crate) fn new_sync() -> Self {
         Waker {
             inner: InnerWaker::Sync(std::thread::current()),
             notified: AtomicBool::new(false),
         }
     }
 
-    fn new_async(waker: TaskWaker) -> Self {
+    pub(crate) fn new_async(waker: TaskWaker) -> Self {
         Waker {
             inner: InnerWaker::Async(waker),
             notified: AtomicBool::new(false),
         }
     }
 
-    pub fn abort(&self) {
-        if self.notified.swap(true, Ordering::Release) {
-            if let InnerWaker::Sync(_) = self.inner {
-                std::thread::park()
-            }
+    /// Abort the waker, so that producers skip it, and return whether it was already notified.
+    ///
+    /// It never parks, so it is the one to use for async wakers, or for sync wakers after the
+    /// thread has parked, as the unpark of the notification may have been consumed.
+    pub fn abort(&self) -> bool {
+        self.notified.swap(true, Ordering::AcqRel)
+    }
+
+    /// Same as [`abort`](Waker::abort), for a waker registered by the current thread which has
+    /// not parked since.
+    ///
+    /// If a sync waker was already notified, the thread parks to absorb the unpark, which is
+    /// bound to follow the notification, so that it does not make the next park return early.
+    /// Async wakers never park.
+    pub fn abort_unparked(&self) -> bool {
+        let notified = self.abort();
+        if let (true, InnerWaker::Sync(_)) = (notified, &self.inner) {
+            std::thread::park();
         }
+        notified
     }
     pub fn wake(&self) -> bool {
         if !self.notified.swap(true, Ordering::Release) {
@@ -53,118 +86,934 @@ impl Waker {
     }
 }
 
+/// Action taken by [`SynchronizedQueue::dequeue_with_park_policy`] after a failed attempt.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub enum ParkAction {
+    /// Execute a spin loop hint before the next attempt.
+    Spin,
+    /// Yield the thread before the next attempt.
+    Yield,
+    /// Park the thread until an item is enqueued.
+    Park,
+}
+
+/// Reason of a [`DetailedDequeue`] returning no item without registering a waker.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Hash)]
+pub enum PollState {
+    /// A producer is publishing an item; the queue will not wake the task, which must poll
+    /// again, e.g. after spinning or yielding.
+    Inconsistent,
+}
+
+/// NUMA node of the CPU running the current thread, or `None` where it cannot be queried.
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+fn current_node() -> Option<usize> {
+    use std::os::raw::{c_int, c_uint};
+
+    extern "C" {
+        fn getcpu(cpu: *mut c_uint, node: *mut c_uint) -> c_int;
+    }
+
+    let (mut cpu, mut node) = (0, 0);
+    // served by the vDSO, without a syscall
+    match unsafe { getcpu(&mut cpu, &mut node) } {
+        0 => Some(node as usize),
+        _ => None,
+    }
+}
+
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+fn current_node() -> Option<usize> {
+    None
+}
+
+/// Wake the wakers queued when called, skipping aborted ones, and return the number woken.
+fn wake_all(wake_queue: &Queue<Arc<Waker>>) -> usize {
+    let mut woken = 0;
+    for _ in 0..wake_queue.approx_len() {
+        match wake_queue.dequeue() {
+            Dequeue::Data(waker) => woken += waker.wake() as usize,
+            _ => break,
+        }
+    }
+    woken
+}
+
 pub struct SynchronizedQueue<T> {
     inner: Queue<T>,
     wake_queue: Queue<Arc<Waker>>,
+    node_wake_queues: Box<[Queue<Arc<Waker>>]>,
+    len_wake_queue: Queue<Arc<Waker>>,
+    dequeue_wake_queue: Queue<Arc<Waker>>,
+    len_waiters: AtomicUsize,
+    batch_awaiters: AtomicUsize,
+    wake_scan_limit: usize,
+    #[cfg(feature = "metrics")]
+    pending_async: AtomicUsize,
+    #[cfg(feature = "debug")]
+    parked_sync: AtomicUsize,
 }
 
 impl<T> SynchronizedQueue<T> {
     pub fn new() -> Self {
+        Self::with_wake_scan_limit(usize::MAX)
+    }
+
+    /// Create a queue whose producers stop looking for a waiter to wake after `limit` dead
+    /// (already notified or aborted) wakers.
+    ///
+    /// It bounds enqueue latency when many waiters abort, but a live waiter left behind is
+    /// only woken by a later enqueue, and is never woken if there is none.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `limit` is zero, as producers would then never wake any waiter.
+    pub fn with_wake_scan_limit(limit: usize) -> Self {
+        assert!(limit > 0, "wake scan limit must be at least one");
+        Self::with_inner(Queue::new(), limit)
+    }
+
+    /// Create a queue counting its items, see [`Queue::with_len_counter`], which is required
+    /// to wait for a length with [`wait_for`](SynchronizedQueue::wait_for) or
+    /// [`poll_batch_ready`](SynchronizedQueue::poll_batch_ready).
+    pub fn with_len_counter() -> Self {
+        Self::with_inner(Queue::with_len_counter(), usize::MAX)
+    }
+
+    /// Create a queue whose producers prefer waking a waiter running on their NUMA node, for
+    /// the consumer to find the item in a nearby cache, and fall back to the other waiters.
+    ///
+    /// Waiters register in the wake queue of their node, taken modulo `nodes`, so waiters are
+    /// only woken in FIFO order within a node. Where the node of the current CPU cannot be
+    /// queried, i.e. outside of Linux with glibc, it behaves like [`new`](Self::new).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `nodes` is zero.
+    pub fn with_local_wake(nodes: usize) -> Self {
+        assert!(nodes > 0, "local wake requires at least one node");
+        let mut queue = Self::new();
+        queue.node_wake_queues = (0..nodes).map(|_| Queue::with_len_counter()).collect();
+        queue
+    }
+
+    fn with_inner(inner: Queue<T>, wake_scan_limit: usize) -> Self {
         SynchronizedQueue {
-            inner: Queue::new(),
-            wake_queue: Queue::new(),
+            inner,
+            wake_queue: Queue::with_len_counter(),
+            node_wake_queues: Box::new([]),
+            len_wake_queue: Queue::with_len_counter(),
+            dequeue_wake_queue: Queue::with_len_counter(),
+            len_waiters: AtomicUsize::new(0),
+            batch_awaiters: AtomicUsize::new(0),
+            wake_scan_limit,
+            #[cfg(feature = "metrics")]
+            pending_async: AtomicUsize::new(0),
+            #[cfg(feature = "debug")]
+            parked_sync: AtomicUsize::new(0),
         }
     }
 
-    pub fn enqueue_notify_spin(&self, value: T, spin: usize) {
-        self.inner.enqueue(value);
-        while let Dequeue::Data(waker) = self.wake_queue.dequeue_spin(spin) {
-            if waker.wake() {
+    /// Wake queue of the current node, if enabled with
+    /// [`with_local_wake`](Self::with_local_wake) and the node can be queried.
+    fn node_wake_queue(&self) -> Option<&Queue<Arc<Waker>>> {
+        if self.node_wake_queues.is_empty() {
+            return None;
+        }
+        let node = current_node()?;
+        Some(&self.node_wake_queues[node % self.node_wake_queues.len()])
+    }
+
+    /// Wake up to `count` waiters, skipping aborted ones, and return the number woken.
+    ///
+    /// It can be used to drive notifications manually, e.g. after operating on the queue
+    /// without notifying. The wake scan limit applies to each call, and to each node with
+    /// [`with_local_wake`](Self::with_local_wake), whose waiters on the current node are
+    /// woken first.
+    pub fn wake_waiters_spin(&self, count: usize, spin: usize) -> usize {
+        let local = self.node_wake_queue();
+        let remote = (self.node_wake_queues.iter())
+            .filter(|wake_queue| !local.is_some_and(|local| std::ptr::eq(local, *wake_queue)));
+        let mut woken = 0;
+        for wake_queue in local.into_iter().chain([&self.wake_queue]).chain(remote) {
+            if woken == count {
                 break;
             }
+            let mut dead_wakers = 0;
+            while woken < count && dead_wakers < self.wake_scan_limit {
+                match wake_queue.dequeue_spin(spin) {
+                    Dequeue::Data(waker) if waker.wake() => woken += 1,
+                    Dequeue::Data(_) => dead_wakers += 1,
+                    _ => break,
+                }
+            }
         }
+        woken
+    }
+
+    pub fn wake_waiters(&self, count: usize) -> usize {
+        self.wake_waiters_spin(count, 0)
+    }
+
+    /// Wake all the current waiters, which register again from their current context if the
+    /// queue is still empty, and return the number woken.
+    ///
+    /// It is needed when registered waiters may no longer be the ones consuming, e.g. when
+    /// consumers migrate to another thread pool or runtime; waiters registering during the call
+    /// are not woken.
+    pub fn rebind_waiters(&self) -> usize {
+        self.flush_wakers()
+    }
+
+    /// Wake all the current waiters, including the ones of
+    /// [`wait_for`](SynchronizedQueue::wait_for) and
+    /// [`enqueue_batch_await`](SynchronizedQueue::enqueue_batch_await), without enqueuing
+    /// anything, and return the number woken; aborted waiters are skipped.
+    ///
+    /// Woken waiters check the queue again, and register again if their condition still does
+    /// not hold.
+    pub fn flush_wakers(&self) -> usize {
+        let nodes: usize = self.node_wake_queues.iter().map(wake_all).sum();
+        wake_all(&self.wake_queue)
+            + nodes
+            + self.wake_len_waiters()
+            + wake_all(&self.dequeue_wake_queue)
+    }
+
+    pub(crate) fn register_waker(&self, waker: Arc<Waker>) {
+        (self.node_wake_queue())
+            .unwrap_or(&self.wake_queue)
+            .enqueue(waker);
+    }
+
+    pub fn enqueue_notify_spin(&self, value: T, spin: usize) {
+        self.inner.enqueue(value);
+        self.wake_waiters_spin(1, spin);
+        self.notify_len_waiters();
     }
 
     pub fn enqueue(&self, value: T) {
         self.enqueue_notify_spin(value, 0)
     }
 
+    fn notify_len_waiters(&self) {
+        // The length is incremented with a sequentially consistent operation before an item
+        // is published, and the waiters counter is incremented the same way before a waiter
+        // checks the length. Either this load sees the waiter, or the waiter sees the new
+        // length, so the fence is only needed with waiters, where it pairs with the one of
+        // `has_len_or_register`.
+        if self.len_waiters.load(Ordering::SeqCst) > 0 {
+            atomic::fence(Ordering::SeqCst);
+            if self.len_wake_queue.approx_len() > 0 {
+                self.wake_len_waiters();
+            }
+        }
+    }
+
+    /// Same as [`wake_all`] for the length waiters, which are no longer counted once dequeued.
+    fn wake_len_waiters(&self) -> usize {
+        let mut woken = 0;
+        for _ in 0..self.len_wake_queue.approx_len() {
+            match self.len_wake_queue.dequeue() {
+                Dequeue::Data(waker) => {
+                    self.len_waiters.fetch_sub(1, Ordering::Relaxed);
+                    woken += waker.wake() as usize;
+                }
+                _ => break,
+            }
+        }
+        woken
+    }
+
+    fn has_len_or_register(&self, n: usize, waker: impl FnOnce() -> Waker) -> bool {
+        if self.inner.approx_len() >= n {
+            return true;
+        }
+        let waker = Arc::new(waker());
+        self.len_waiters.fetch_add(1, Ordering::SeqCst);
+        self.len_wake_queue.enqueue(waker.clone());
+        // pairs with the fence of `notify_len_waiters`
+        atomic::fence(Ordering::SeqCst);
+        if self.inner.approx_len() >= n {
+            waker.abort_unparked();
+            return true;
+        }
+        false
+    }
+
+    /// Park the thread until the queue holds at least `n` items, as counted by
+    /// [`Queue::approx_len`], without consuming them.
+    ///
+    /// Length waiters are woken by every enqueue and check the length again, so they should be
+    /// few. The items may be consumed by other consumers as soon as it returns.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the queue was not created with
+    /// [`with_len_counter`](SynchronizedQueue::with_len_counter).
+    pub fn wait_for_blocking(&self, n: usize) {
+        while !self.has_len_or_register(n, Waker::new_sync) {
+            std::thread::park();
+        }
+    }
+
+    /// Async version of [`wait_for_blocking`](SynchronizedQueue::wait_for_blocking).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the queue was not created with
+    /// [`with_len_counter`](SynchronizedQueue::with_len_counter).
+    pub async fn wait_for(&self, n: usize) {
+        poll_fn(
+            |cx| match self.has_len_or_register(n, || Waker::new_async(cx.waker().clone())) {
+                true => Poll::Ready(()),
+                false => Poll::Pending,
+            },
+        )
+        .await
+    }
+
+    /// Publish the items as with [`Queue::enqueue_atomic`], notifying a waiter for each of them,
+    /// and return a future resolving once they have all been dequeued.
+    ///
+    /// The items are published by the call, not when the future is first polled. Completion is
+    /// tracked by dequeue index, so an item counts as consumed as soon as it is dequeued, whether
+    /// or not the consumer processes it, e.g. if it drops it. A consumer which fails to take the
+    /// last item because its producer is still publishing the next one advances the index before
+    /// rolling it back, so the future may resolve while that item is still queued. Using
+    /// [`Queue::enqueue_with_index`] concurrently breaks the tracking.
+    pub fn enqueue_batch_await<I: IntoIterator<Item = T>>(&self, iter: I) -> BatchConsumed<'_, T> {
+        let indices = self.inner.enqueue_iter_indexed(iter);
+        let count = indices.end.wrapping_sub(indices.start);
+        if count > 0 {
+            self.batch_awaiters.fetch_add(1, Ordering::SeqCst);
+            self.wake_waiters(count);
+            self.notify_len_waiters();
+        }
+        BatchConsumed {
+            queue: self,
+            end: indices.end,
+            waker: None,
+            counted: count > 0,
+        }
+    }
+
+    fn notify_dequeued(&self, res: Dequeue<T>) -> Dequeue<T> {
+        // The dequeue index is updated with a sequentially consistent compare-and-swap, and
+        // the awaiters counter is incremented the same way before an awaiter loads the index.
+        // Either this load sees the awaiter, or the awaiter sees the new index, so the fence
+        // is only needed with awaiters, where it pairs with the one of `BatchConsumed::poll`.
+        if let Dequeue::Data(_) = res {
+            if self.batch_awaiters.load(Ordering::SeqCst) > 0 {
+                atomic::fence(Ordering::SeqCst);
+                if self.dequeue_wake_queue.approx_len() > 0 {
+                    wake_all(&self.dequeue_wake_queue);
+                }
+            }
+        }
+        res
+    }
+
+    /// Return `Ready` if the queue holds at least `min` items, as counted by
+    /// [`Queue::approx_len`], registering the waker of `cx` otherwise, e.g. to implement a
+    /// batch consumer waking only once a full batch has accumulated.
+    ///
+    /// The registered waker is woken on every enqueue, like the ones of
+    /// [`wait_for`](SynchronizedQueue::wait_for), and the task must poll again. As the queue
+    /// cannot be closed, it never becomes ready with fewer items.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the queue was not created with
+    /// [`with_len_counter`](SynchronizedQueue::with_len_counter).
+    pub fn poll_batch_ready(&self, cx: &mut Context<'_>, min: usize) -> Poll<()> {
+        match self.has_len_or_register(min, || Waker::new_async(cx.waker().clone())) {
+            true => Poll::Ready(()),
+            false => Poll::Pending,
+        }
+    }
+
     pub fn try_dequeue_spin(&self, spin: usize) -> Dequeue<T> {
-        self.inner.dequeue_spin(spin)
+        self.notify_dequeued(self.inner.dequeue_spin(spin))
+    }
+
+    /// See [`Queue::dequeue_spin_or_none`]; it never parks.
+    pub fn dequeue_spin_or_none(&self, spin: usize) -> Option<T> {
+        self.try_dequeue_spin(spin).data()
     }
 
     pub fn try_dequeue(&self) -> Dequeue<T> {
         self.try_dequeue_spin(0)
     }
 
-    fn dequeue_sync(&self, spin: usize, timeout: Option<Duration>) -> Dequeue<T> {
-        let end = timeout.map(|t| Instant::now() + t);
+    /// Retry [`try_dequeue`](SynchronizedQueue::try_dequeue) until data is returned or `deadline`
+    /// is reached, without ever parking the thread; see [`Queue::dequeue_spin_until`].
+    pub fn try_dequeue_spin_until(&self, deadline: Instant) -> Dequeue<T> {
+        self.notify_dequeued(self.inner.dequeue_spin_until(deadline))
+    }
+
+    /// Move up to `max` available items to `dest`, notifying its waiters, and return the count.
+    ///
+    /// Items keep their relative order, but the transfer is not atomic across the two queues.
+    pub fn transfer_to(&self, dest: &SynchronizedQueue<T>, max: usize) -> usize {
+        let mut count = 0;
+        while count < max {
+            match self.try_dequeue() {
+                Dequeue::Data(value) => dest.enqueue(value),
+                _ => break,
+            }
+            count += 1;
+        }
+        count
+    }
+
+    /// Register a waker, then park with `park` unless an item has arrived in the meantime.
+    ///
+    /// The waker is aborted after parking, as `park` may return without notification, e.g. on
+    /// timeout; otherwise it would stay registered, and a producer would spend a wakeup on it.
+    /// Without item, return whether the waker was notified.
+    fn register_or_park(&self, spin: usize, park: impl FnOnce()) -> Result<T, bool> {
+        let waker = Arc::new(Waker::new_sync());
+        self.register_waker(waker.clone());
+        if let Dequeue::Data(value) = self.try_dequeue_spin(spin) {
+            waker.abort_unparked();
+            return Ok(value);
+        }
+        #[cfg(feature = "debug")]
+        self.parked_sync.fetch_add(1, Ordering::Relaxed);
+        park();
+        #[cfg(feature = "debug")]
+        self.parked_sync.fetch_sub(1, Ordering::Relaxed);
+        Err(waker.abort())
+    }
+
+    fn dequeue_sync(
+        &self,
+        spin: usize,
+        timeout: Option<Duration>,
+        clock: &impl Clock,
+    ) -> Dequeue<T> {
+        let end = timeout.map(|t| clock.now() + t);
         loop {
             if let res @ Dequeue::Data(_) = self.try_dequeue_spin(spin) {
                 return res;
             }
-            let waker = Arc::new(Waker::new_sync());
-            self.wake_queue.enqueue(waker.clone());
-            if let res @ Dequeue::Data(_) = self.try_dequeue_spin(spin) {
-                waker.abort();
+            let park = || match end {
+                Some(end) => std::thread::park_timeout(end.saturating_duration_since(clock.now())),
+                None => std::thread::park(),
+            };
+            let notified = match self.register_or_park(spin, park) {
+                Ok(value) => return Dequeue::Data(value),
+                Err(notified) => notified,
+            };
+            if end.is_some_and(|end| clock.now() >= end) {
+                let res = self.try_dequeue_spin(spin);
+                // the notification is forwarded if its item is left to another consumer
+                if notified && !matches!(res, Dequeue::Data(_)) {
+                    self.wake_waiters(1);
+                }
                 return res;
             }
-            if let Some(end) = end {
-                std::thread::park_timeout(end - Instant::now());
-                if Instant::now() >= end {
-                    return self.try_dequeue_spin(spin);
+        }
+    }
+
+    /// Dequeue an item, letting `policy` decide what to do after each failed attempt; it
+    /// receives the number of failed attempts so far, starting at 0.
+    ///
+    /// [`ParkAction::Park`] registers a waker like [`dequeue`](SynchronizedQueue::dequeue), so
+    /// the thread is unparked by a subsequent enqueue; the policy is called again after.
+    pub fn dequeue_with_park_policy(&self, mut policy: impl FnMut(u32) -> ParkAction) -> T {
+        let mut attempts = 0;
+        loop {
+            if let Dequeue::Data(value) = self.try_dequeue() {
+                return value;
+            }
+            match policy(attempts) {
+                ParkAction::Spin => std::hint::spin_loop(),
+                ParkAction::Yield => std::thread::yield_now(),
+                ParkAction::Park => {
+                    if let Ok(value) = self.register_or_park(0, std::thread::park) {
+                        return value;
+                    }
                 }
-            } else {
-                std::thread::park();
             }
+            attempts = attempts.saturating_add(1);
         }
     }
 
     pub fn dequeue_spin(&self, spin: usize) -> T {
-        self.dequeue_sync(spin, None).data().unwrap()
+        self.dequeue_sync(spin, None, &SystemClock).data().unwrap()
     }
 
     pub fn dequeue(&self) -> T {
         self.dequeue_spin(0)
     }
 
+    fn matches(&self, value: T, pred: impl Fn(&T) -> bool) -> Result<T, T> {
+        match panic::catch_unwind(AssertUnwindSafe(|| pred(&value))) {
+            Ok(true) => Ok(value),
+            Ok(false) => Err(value),
+            Err(payload) => {
+                self.enqueue(value);
+                panic::resume_unwind(payload)
+            }
+        }
+    }
+
+    /// Dequeue items until one matches `pred`, dropping the others.
+    ///
+    /// If `pred` panics, the item being tested is enqueued back before the panic is resumed,
+    /// so that no item is lost; it goes to the back of the queue, behind the items enqueued
+    /// after it, which breaks FIFO order.
+    pub fn dequeue_until(&self, pred: impl Fn(&T) -> bool) -> T {
+        loop {
+            if let Ok(value) = self.matches(self.dequeue(), &pred) {
+                return value;
+            }
+        }
+    }
+
+    /// Dequeue items until one matches `pred`, enqueuing the others back.
+    ///
+    /// Re-enqueued items go behind the items already queued, so their order relative to them
+    /// is lost. As they keep the queue non-empty, the method never parks when no item matches,
+    /// and spins until a matching one arrives. If `pred` panics, the item being tested is
+    /// enqueued back, at the back of the queue too, before the panic is resumed.
+    pub fn dequeue_until_requeue(&self, pred: impl Fn(&T) -> bool) -> T {
+        loop {
+            match self.matches(self.dequeue(), &pred) {
+                Ok(value) => return value,
+                Err(value) => self.enqueue(value),
+            }
+        }
+    }
+
     pub fn dequeue_timeout_spin(&self, timeout: Duration, spin: usize) -> Dequeue<T> {
-        self.dequeue_sync(spin, Some(timeout))
+        self.dequeue_sync(spin, Some(timeout), &SystemClock)
+    }
+
+    /// Same as [`dequeue_timeout`](SynchronizedQueue::dequeue_timeout), with the deadline
+    /// computed and checked using `clock`; the thread is still parked for real durations.
+    pub fn dequeue_timeout_with_clock(&self, timeout: Duration, clock: &impl Clock) -> Dequeue<T> {
+        self.dequeue_sync(0, Some(timeout), clock)
     }
 
     pub fn dequeue_timeout(&self, timeout: Duration) -> Dequeue<T> {
         self.dequeue_timeout_spin(timeout, 0)
     }
 
+    /// Collect up to `max` items, parking between them, until `deadline` is reached.
+    ///
+    /// Items collected before the deadline are returned, possibly none; when the deadline has
+    /// already passed, only the items immediately available are collected.
+    pub fn dequeue_batch_until(&self, max: usize, deadline: Instant) -> Vec<T> {
+        let mut items = Vec::new();
+        while items.len() < max {
+            let res = match deadline.checked_duration_since(Instant::now()) {
+                Some(timeout) if !timeout.is_zero() => self.dequeue_timeout(timeout),
+                _ => self.try_dequeue(),
+            };
+            match res {
+                Dequeue::Data(value) => items.push(value),
+                _ => break,
+            }
+        }
+        items
+    }
+
     pub fn dequeue_async_spin(&self, spin: usize) -> impl Future<Output = T> + '_ {
-        DequeueFuture { queue: self, spin }
+        DequeueFuture::new(self, spin)
+    }
+
+    /// Return a future reporting whether the queue will wake the task, see
+    /// [`DetailedDequeue`].
+    pub fn dequeue_detailed(&self, spin: usize) -> DetailedDequeue<'_, T> {
+        DetailedDequeue {
+            future: DequeueFuture::new(self, spin),
+        }
+    }
+
+    /// Return a snapshot of the queue observability counters, including the registered
+    /// waiters, see [`QueueHealth`].
+    pub fn health(&self) -> QueueHealth {
+        QueueHealth {
+            waiters: self.wake_queue.approx_len()
+                + (self.node_wake_queues.iter())
+                    .map(Queue::approx_len)
+                    .sum::<usize>()
+                + self.len_wake_queue.approx_len()
+                + self.dequeue_wake_queue.approx_len(),
+            ..self.inner.health()
+        }
+    }
+
+    /// Number of async dequeue futures which returned `Pending` and have neither completed nor
+    /// been dropped; a number growing over time may reveal futures never woken or leaked.
+    #[cfg(feature = "metrics")]
+    pub fn pending_async(&self) -> usize {
+        self.pending_async.load(Ordering::Relaxed)
+    }
+
+    /// Return whether an item is ready while consumers are parked or pending, which suggests a
+    /// lost wakeup, e.g. a periodic check in a monitoring thread.
+    ///
+    /// A waiter takes some time to resume after being woken, so a `true` result can be a
+    /// transient race and must be confirmed over several samples before being reported.
+    #[cfg(feature = "debug")]
+    pub fn detect_lost_wakeups(&self) -> bool {
+        let waiters = self.parked_sync.load(Ordering::Relaxed) + self.pending_async();
+        waiters > 0 && self.inner.has_ready_item()
     }
 
     pub fn dequeue_async(&self) -> impl Future<Output = T> + '_ {
         self.dequeue_async_spin(0)
     }
+
+    /// Async dequeue giving up with `None` when polled `max_polls` times without data.
+    ///
+    /// The number of polls is only a rough proxy for time, as it depends on how often the
+    /// future is woken, but it bounds the wait without requiring a timer.
+    pub fn dequeue_async_bounded_polls(
+        &self,
+        max_polls: usize,
+    ) -> impl Future<Output = Option<T>> + '_ {
+        BoundedPollsFuture {
+            future: DequeueFuture::new(self, 0),
+            polls: 0,
+            max_polls,
+        }
+    }
+
+    /// Batch the items by time `window`, measured with `timer`.
+    ///
+    /// Each call to [`Windowed::next`] yields the items arrived during a window, including the
+    /// ones already queued before the first window; empty windows yield empty batches unless
+    /// [`Windowed::skip_empty`] is used.
+    pub fn windowed<'a, Tm: Timer>(
+        &'a self,
+        window: Duration,
+        timer: &'a Tm,
+    ) -> Windowed<'a, T, Tm> {
+        Windowed {
+            queue: self,
+            window,
+            timer,
+            skip_empty: false,
+            yield_budget: DEFAULT_YIELD_BUDGET,
+            items: Vec::new(),
+        }
+    }
+
+    /// Race an async dequeue against `timer`; when the timer fires first, a last try is
+    /// returned.
+    ///
+    /// On timeout, or if the future is dropped, the registered waker is aborted, and a
+    /// notification it already received is forwarded to another waiter.
+    pub async fn dequeue_async_timeout_with(
+        &self,
+        timeout: Duration,
+        timer: &impl Timer,
+    ) -> Dequeue<T> {
+        let mut dequeue = DequeueFuture::new(self, 0);
+        let mut sleep = pin!(timer.sleep(timeout));
+        poll_fn(|cx| {
+            if let Poll::Ready(res) = Pin::new(&mut dequeue).poll(cx) {
+                Poll::Ready(Dequeue::Data(res))
+            } else if sleep.as_mut().poll(cx).is_ready() {
+                let res = self.try_dequeue();
+                if dequeue.unregister() && !matches!(res, Dequeue::Data(_)) {
+                    self.wake_waiters(1);
+                }
+                Poll::Ready(res)
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+}
+
+impl<T> Default for SynchronizedQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Future returned by [`SynchronizedQueue::enqueue_batch_await`].
+pub struct BatchConsumed<'a, T> {
+    queue: &'a SynchronizedQueue<T>,
+    end: usize,
+    waker: Option<Arc<Waker>>,
+    /// Whether the future is counted in the awaiters of the queue, until it resolves.
+    counted: bool,
+}
+
+impl<T> BatchConsumed<'_, T> {
+    fn consumed(&self) -> bool {
+        // indices wrap, so compare the signed distance to the end of the batch
+        self.queue.inner.dequeue_index().wrapping_sub(self.end) as isize >= 0
+    }
+
+    fn release(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.abort();
+        }
+        if std::mem::take(&mut self.counted) {
+            self.queue.batch_awaiters.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+impl<T> Future for BatchConsumed<'_, T> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        if let Some(waker) = this.waker.take() {
+            waker.abort();
+        }
+        if !this.counted || this.consumed() {
+            this.release();
+            return Poll::Ready(());
+        }
+        let waker = Arc::new(Waker::new_async(cx.waker().clone()));
+        this.queue.dequeue_wake_queue.enqueue(waker.clone());
+        // pairs with the fence of `notify_dequeued`
+        atomic::fence(Ordering::SeqCst);
+        if this.consumed() {
+            waker.abort();
+            this.release();
+            return Poll::Ready(());
+        }
+        this.waker = Some(waker);
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for BatchConsumed<'_, T> {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+const DEFAULT_YIELD_BUDGET: usize = 128;
+
+/// Return `Pending` once, waking the task immediately, to let the executor run other tasks.
+async fn yield_now() {
+    let mut yielded = false;
+    poll_fn(|cx| {
+        if yielded {
+            return Poll::Ready(());
+        }
+        yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    })
+    .await
+}
+
+/// Time-based batches of a [`SynchronizedQueue`], see
+/// [`windowed`](SynchronizedQueue::windowed).
+pub struct Windowed<'a, T, Tm> {
+    queue: &'a SynchronizedQueue<T>,
+    window: Duration,
+    timer: &'a Tm,
+    skip_empty: bool,
+    yield_budget: usize,
+    items: Vec<T>,
+}
+
+impl<'a, T, Tm: Timer> Windowed<'a, T, Tm> {
+    /// Skip the windows in which no item arrived, instead of yielding empty batches.
+    pub fn skip_empty(mut self) -> Self {
+        self.skip_empty = true;
+        self
+    }
+
+    /// Yield to the executor every `budget` items collected by [`next`](Windowed::next), so
+    /// that a large batch is collected across several polls; the default budget is 128.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `budget` is zero.
+    pub fn yield_budget(mut self, budget: usize) -> Self {
+        assert!(budget > 0, "yield budget must not be zero");
+        self.yield_budget = budget;
+        self
+    }
+
+    /// Wait for the end of the next window, and return the items available at that time.
+    ///
+    /// If the future is dropped while yielding, the items collected so far are kept and
+    /// returned by the next call.
+    pub async fn next(&mut self) -> Vec<T> {
+        loop {
+            self.timer.sleep(self.window).await;
+            let mut collected = 0;
+            while let Dequeue::Data(value) = self.queue.try_dequeue() {
+                self.items.push(value);
+                collected += 1;
+                if collected % self.yield_budget == 0 {
+                    yield_now().await;
+                }
+            }
+            if !(self.items.is_empty() && self.skip_empty) {
+                return std::mem::take(&mut self.items);
+            }
+        }
+    }
+}
+
+/// Async dequeue keeping its registered waker, so that it is aborted when the future polls
+/// again, gives up or is dropped; otherwise, a producer would spend a wakeup on it.
+/// Future returned by [`SynchronizedQueue::dequeue_detailed`], which can be polled again after
+/// it completes.
+///
+/// - `Ready(Ok(item))`: an item was dequeued.
+/// - `Pending`: the queue was empty, and the waker of the context is registered; it is woken
+///   by a subsequent enqueue (or [`wake_waiters`](SynchronizedQueue::wake_waiters)).
+/// - `Ready(Err(state))`: no item and no waker registered, see [`PollState`].
+///
+/// The registered waker is aborted when the future is polled again or dropped; if it was
+/// notified in between, the notification is forwarded to another waiter.
+pub struct DetailedDequeue<'a, T> {
+    future: DequeueFuture<'a, T>,
+}
+
+impl<'a, T> Future for DetailedDequeue<'a, T> {
+    type Output = Result<T, PollState>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let future = &mut self.get_mut().future;
+        let notified = future.unregister();
+        let poll = match future.queue.try_dequeue_spin(future.spin) {
+            Dequeue::Data(value) => Poll::Ready(Ok(value)),
+            Dequeue::Inconsistent => {
+                if notified {
+                    future.queue.wake_waiters(1);
+                }
+                Poll::Ready(Err(PollState::Inconsistent))
+            }
+            Dequeue::Empty => future.try_poll(cx).map(Ok),
+        };
+        #[cfg(feature = "metrics")]
+        future.set_pending(poll.is_pending());
+        poll
+    }
 }
 
 struct DequeueFuture<'a, T> {
     queue: &'a SynchronizedQueue<T>,
     spin: usize,
+    waker: Option<Arc<Waker>>,
+    #[cfg(feature = "metrics")]
+    pending: bool,
 }
 
-impl<'a, T> Future for DequeueFuture<'a, T> {
-    type Output = T;
+impl<'a, T> DequeueFuture<'a, T> {
+    fn new(queue: &'a SynchronizedQueue<T>, spin: usize) -> Self {
+        DequeueFuture {
+            queue,
+            spin,
+            waker: None,
+            #[cfg(feature = "metrics")]
+            pending: false,
+        }
+    }
 
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    /// Abort the registered waker, if any, and return whether it was notified.
+    fn unregister(&mut self) -> bool {
+        self.waker.take().is_some_and(|waker| waker.abort())
+    }
+
+    /// Abort the registered waker, forwarding its notification to another waiter, as the
+    /// future gives up without the item it was notified for.
+    fn cancel(&mut self) {
+        if self.unregister() {
+            self.queue.wake_waiters(1);
+        }
+    }
+
+    fn try_poll(&mut self, cx: &mut Context<'_>) -> Poll<T> {
+        self.unregister();
         if let Dequeue::Data(res) = self.queue.try_dequeue_spin(self.spin) {
             Poll::Ready(res)
         } else {
             let waker = Arc::new(Waker::new_async(cx.waker().clone()));
-            self.queue.wake_queue.enqueue(waker.clone());
+            self.queue.register_waker(waker.clone());
             if let Dequeue::Data(res) = self.queue.try_dequeue_spin(self.spin) {
                 waker.abort();
                 Poll::Ready(res)
             } else {
+                self.waker = Some(waker);
                 Poll::Pending
             }
         }
     }
+
+    #[cfg(feature = "metrics")]
+    fn set_pending(&mut self, pending: bool) {
+        match (self.pending, pending) {
+            (false, true) => self.queue.pending_async.fetch_add(1, Ordering::Relaxed),
+            (true, false) => self.queue.pending_async.fetch_sub(1, Ordering::Relaxed),
+            _ => return,
+        };
+        self.pending = pending;
+    }
+}
+
+impl<'a, T> Future for DequeueFuture<'a, T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let poll = this.try_poll(cx);
+        #[cfg(feature = "metrics")]
+        this.set_pending(poll.is_pending());
+        poll
+    }
+}
+
+struct BoundedPollsFuture<'a, T> {
+    future: DequeueFuture<'a, T>,
+    polls: usize,
+    max_polls: usize,
+}
+
+impl<'a, T> Future for BoundedPollsFuture<'a, T> {
+    type Output = Option<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        this.polls += 1;
+        match Pin::new(&mut this.future).poll(cx) {
+            Poll::Ready(res) => Poll::Ready(Some(res)),
+            Poll::Pending if this.polls >= this.max_polls => {
+                this.future.cancel();
+                Poll::Ready(None)
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<'a, T> Drop for DequeueFuture<'a, T> {
+    fn drop(&mut self) {
+        self.cancel();
+        #[cfg(feature = "metrics")]
+        self.set_pending(false)
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::cell::Cell;
+    use std::sync::atomic::Ordering;
     use std::sync::Arc;
-    use std::time::Duration;
+    use std::time::{Duration, Instant};
 
-    use crate::synchronized::SynchronizedQueue;
+    use crate::synchronized::{Clock, ParkAction, SynchronizedQueue, Timer, Waker};
+    use crate::{Dequeue, QueueHealth};
 
     #[test]
     fn synchronized() {
@@ -178,6 +1027,400 @@ mod tests {
         }
         assert_eq!(queue.dequeue(), 0);
     }
+    #[test]
+    fn transfer_to() {
+        let stage1 = Arc::new(SynchronizedQueue::new());
+        let stage2 = Arc::new(SynchronizedQueue::new());
+        let consumer = {
+            let stage2 = stage2.clone();
+            std::thread::spawn(move || (0..5).map(|_| stage2.dequeue()).collect::<Vec<_>>())
+        };
+        for i in 0..5 {
+            stage1.enqueue(i);
+        }
+        assert_eq!(stage1.transfer_to(&stage2, 3), 3);
+        assert_eq!(stage1.transfer_to(&stage2, 3), 2);
+        assert_eq!(stage1.transfer_to(&stage2, 3), 0);
+        assert_eq!(consumer.join().unwrap(), [0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn try_dequeue_spin_until() {
+        let queue = SynchronizedQueue::new();
+        let deadline = Instant::now() + Duration::from_millis(10);
+        assert_eq!(queue.try_dequeue_spin_until(deadline), Dequeue::Empty);
+        assert!(Instant::now() >= deadline);
+        queue.enqueue(0);
+        assert_eq!(queue.try_dequeue_spin_until(deadline), Dequeue::Data(0));
+    }
+
+    #[test]
+    fn dequeue_batch_until() {
+        let queue = Arc::new(SynchronizedQueue::new());
+        queue.enqueue(0);
+        let producer = {
+            let queue = queue.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(10));
+                queue.enqueue(1);
+            })
+        };
+        let deadline = Instant::now() + Duration::from_millis(100);
+        assert_eq!(queue.dequeue_batch_until(3, deadline), [0, 1]);
+        assert!(Instant::now() >= deadline);
+        producer.join().unwrap();
+        queue.enqueue(2);
+        queue.enqueue(3);
+        assert_eq!(queue.dequeue_batch_until(3, deadline), [2, 3]);
+        assert_eq!(queue.dequeue_batch_until(3, deadline), []);
+    }
+
+    #[test]
+    fn abort_race() {
+        let waker = Waker::new_sync();
+        assert!(!waker.abort_unparked());
+        // the unpark of a notification racing with the abort is absorbed
+        let waker = Waker::new_sync();
+        assert!(waker.wake());
+        assert!(waker.abort_unparked());
+        let start = Instant::now();
+        std::thread::park_timeout(Duration::from_millis(20));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        // async wakers never park, as there is no unpark to absorb
+        let waker = Waker::new_async(futures::task::noop_waker());
+        assert!(waker.wake());
+        assert!(waker.abort_unparked());
+        assert!(waker.abort());
+    }
+
+    #[test]
+    fn timed_out_waiter() {
+        let queue = Arc::new(SynchronizedQueue::new());
+        let timeout = Duration::from_millis(1);
+        assert_eq!(queue.dequeue_timeout(timeout), Dequeue::Empty);
+        let consumer = {
+            let queue = queue.clone();
+            std::thread::spawn(move || queue.dequeue())
+        };
+        while queue.wake_queue.approx_len() < 2 {
+            std::thread::yield_now();
+        }
+        queue.enqueue(0);
+        assert_eq!(consumer.join().unwrap(), 0);
+    }
+
+    #[test]
+    fn wake_scan_limit() {
+        let queue = SynchronizedQueue::with_wake_scan_limit(2);
+        for _ in 0..3 {
+            let waker = Arc::new(Waker::new_sync());
+            waker.wake();
+            queue.wake_queue.enqueue(waker);
+        }
+        let live = Arc::new(Waker::new_sync());
+        queue.wake_queue.enqueue(live.clone());
+        queue.enqueue(0);
+        assert!(!live.notified.load(Ordering::Acquire));
+        queue.enqueue(1);
+        assert!(live.notified.load(Ordering::Acquire));
+    }
+
+    #[test]
+    #[cfg(all(target_os = "linux", target_env = "gnu"))]
+    fn local_wake() {
+        let queue = SynchronizedQueue::with_local_wake(2);
+        let local = crate::synchronized::current_node().unwrap() % 2;
+        let wakers: Vec<_> = (0..3).map(|_| Arc::new(Waker::new_sync())).collect();
+        queue.node_wake_queues[1 - local].enqueue(wakers[0].clone());
+        queue.wake_queue.enqueue(wakers[1].clone());
+        queue.register_waker(wakers[2].clone());
+        let notified = || {
+            let notified = wakers.iter().map(|w| w.notified.load(Ordering::Acquire));
+            notified.collect::<Vec<_>>()
+        };
+        queue.enqueue(0);
+        assert_eq!(notified(), [false, false, true]);
+        queue.enqueue(1);
+        assert_eq!(notified(), [false, true, true]);
+        queue.enqueue(2);
+        assert_eq!(notified(), [true; 3]);
+        assert_eq!(queue.health().waiters, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "wake scan limit")]
+    fn zero_wake_scan_limit() {
+        SynchronizedQueue::<usize>::with_wake_scan_limit(0);
+    }
+
+    #[test]
+    fn wake_waiters() {
+        let queue = SynchronizedQueue::<usize>::new();
+        assert_eq!(queue.wake_waiters(1), 0);
+        let wakers: Vec<_> = (0..6).map(|_| Arc::new(Waker::new_sync())).collect();
+        for (i, waker) in wakers.iter().enumerate() {
+            if i % 2 == 1 {
+                waker.abort();
+            }
+            queue.wake_queue.enqueue(waker.clone());
+        }
+        assert_eq!(queue.wake_waiters(0), 0);
+        assert_eq!(queue.wake_waiters(1), 1);
+        assert!(wakers[0].notified.load(Ordering::Acquire));
+        assert!(!wakers[2].notified.load(Ordering::Acquire));
+        assert_eq!(queue.wake_waiters(usize::MAX), 2);
+        assert!(wakers.iter().all(|w| w.notified.load(Ordering::Acquire)));
+        assert_eq!(queue.wake_waiters(usize::MAX), 0);
+    }
+
+    #[test]
+    fn rebind_waiters() {
+        let queue = Arc::new(SynchronizedQueue::new());
+        assert_eq!(queue.rebind_waiters(), 0);
+        let consumer = {
+            let queue = queue.clone();
+            std::thread::spawn(move || queue.dequeue())
+        };
+        while queue.wake_queue.approx_len() == 0 {
+            std::thread::yield_now();
+        }
+        assert_eq!(queue.rebind_waiters(), 1);
+        while queue.wake_queue.approx_len() == 0 {
+            std::thread::yield_now();
+        }
+        queue.enqueue(0);
+        assert_eq!(consumer.join().unwrap(), 0);
+    }
+
+    #[test]
+    fn wait_for() {
+        let queue = Arc::new(SynchronizedQueue::with_len_counter());
+        queue.wait_for_blocking(0);
+        let waiters: Vec<_> = [false, true]
+            .map(|is_async| {
+                let queue = queue.clone();
+                std::thread::spawn(move || {
+                    if is_async {
+                        futures::executor::block_on(queue.wait_for(5));
+                    } else {
+                        queue.wait_for_blocking(5);
+                    }
+                    queue.inner.approx_len()
+                })
+            })
+            .into();
+        for i in 0..4 {
+            queue.enqueue(i);
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert!(waiters.iter().all(|w| !w.is_finished()));
+        queue.enqueue(4);
+        for waiter in waiters {
+            assert_eq!(waiter.join().unwrap(), 5);
+        }
+        // aborted registrations stay counted until dequeued
+        queue.flush_wakers();
+        assert_eq!(queue.len_waiters.load(Ordering::Relaxed), 0);
+        assert_eq!(queue.try_dequeue(), Dequeue::Data(0));
+    }
+
+    #[test]
+    fn poll_batch_ready() {
+        use std::task::{Context, Poll};
+
+        let queue = Arc::new(SynchronizedQueue::with_len_counter());
+        let producer = {
+            let queue = queue.clone();
+            std::thread::spawn(move || {
+                for i in 0..5 {
+                    std::thread::sleep(Duration::from_millis(1));
+                    queue.enqueue(i);
+                }
+            })
+        };
+        let mut polls = 0;
+        futures::executor::block_on(std::future::poll_fn(|cx: &mut Context| {
+            polls += 1;
+            queue.poll_batch_ready(cx, 5)
+        }));
+        producer.join().unwrap();
+        assert!(polls > 1);
+        assert_eq!(queue.inner.approx_len(), 5);
+        let waker = futures::task::noop_waker();
+        assert_eq!(
+            queue.poll_batch_ready(&mut Context::from_waker(&waker), 5),
+            Poll::Ready(())
+        );
+    }
+
+    #[test]
+    fn enqueue_batch_await() {
+        use std::future::Future;
+        use std::pin::pin;
+        use std::task::{Context, Poll};
+
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        let queue = SynchronizedQueue::with_len_counter();
+        queue.enqueue(-1);
+        let mut batch = pin!(queue.enqueue_batch_await(0..5));
+        assert_eq!(queue.inner.approx_len(), 6);
+        assert_eq!(batch.as_mut().poll(&mut cx), Poll::Pending);
+        assert_eq!(queue.try_dequeue(), Dequeue::Data(-1));
+        for i in 0..4 {
+            assert_eq!(queue.try_dequeue(), Dequeue::Data(i));
+            assert_eq!(batch.as_mut().poll(&mut cx), Poll::Pending);
+        }
+        assert_eq!(queue.dequeue_wake_queue.approx_len(), 1);
+        assert_eq!(queue.try_dequeue(), Dequeue::Data(4));
+        assert_eq!(queue.dequeue_wake_queue.approx_len(), 0);
+        assert_eq!(batch.poll(&mut cx), Poll::Ready(()));
+        assert_eq!(queue.batch_awaiters.load(Ordering::Relaxed), 0);
+        futures::executor::block_on(queue.enqueue_batch_await([]));
+        drop(queue.enqueue_batch_await([5]));
+        assert_eq!(queue.batch_awaiters.load(Ordering::Relaxed), 0);
+        assert_eq!(queue.try_dequeue(), Dequeue::Data(5));
+    }
+
+    #[test]
+    fn health() {
+        let queue = Arc::new(SynchronizedQueue::with_len_counter());
+        let consumer = {
+            let queue = queue.clone();
+            std::thread::spawn(move || queue.dequeue())
+        };
+        while queue.health().waiters == 0 {
+            std::thread::yield_now();
+        }
+        queue.enqueue(0);
+        assert_eq!(consumer.join().unwrap(), 0);
+        (1..4).for_each(|i| queue.enqueue(i));
+        queue.try_dequeue();
+        let metrics = |n| cfg!(feature = "metrics").then_some(n);
+        assert_eq!(
+            queue.health(),
+            QueueHealth {
+                approx_len: Some(2),
+                max_depth: Some(3),
+                waiters: 0,
+                cached_nodes: 1,
+                total_enqueued: metrics(4),
+                total_dequeued: metrics(2),
+            }
+        );
+    }
+
+    #[test]
+    fn flush_wakers() {
+        let queue = Arc::new(SynchronizedQueue::new());
+        let aborted = Arc::new(Waker::new_sync());
+        aborted.abort();
+        queue.wake_queue.enqueue(aborted);
+        let consumers: Vec<_> = (0..3)
+            .map(|_| {
+                let queue = queue.clone();
+                std::thread::spawn(move || queue.dequeue())
+            })
+            .collect();
+        while queue.wake_queue.approx_len() < 4 {
+            std::thread::yield_now();
+        }
+        assert_eq!(queue.flush_wakers(), 3);
+        while queue.wake_queue.approx_len() < 3 {
+            std::thread::yield_now();
+        }
+        (0..3).for_each(|i| queue.enqueue(i));
+        let mut values: Vec<_> = consumers.into_iter().map(|c| c.join().unwrap()).collect();
+        values.sort();
+        assert_eq!(values, [0, 1, 2]);
+    }
+
+    #[test]
+    fn dequeue_with_park_policy() {
+        let queue = Arc::new(SynchronizedQueue::new());
+        {
+            let queue = queue.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(10));
+                queue.enqueue(0)
+            });
+        }
+        let mut attempts = Vec::new();
+        let policy = |attempt| {
+            attempts.push(attempt);
+            match attempt {
+                0 | 1 => ParkAction::Spin,
+                _ => ParkAction::Park,
+            }
+        };
+        assert_eq!(queue.dequeue_with_park_policy(policy), 0);
+        assert_eq!(attempts[..3], [0, 1, 2]);
+    }
+
+    #[test]
+    fn dequeue_until() {
+        let queue = SynchronizedQueue::new();
+        for i in 0..4 {
+            queue.enqueue(i);
+        }
+        assert_eq!(queue.dequeue_until(|&i| i == 2), 2);
+        assert_eq!(queue.dequeue(), 3);
+        assert_eq!(queue.try_dequeue(), Dequeue::Empty);
+        for i in 0..4 {
+            queue.enqueue(i);
+        }
+        assert_eq!(queue.dequeue_until_requeue(|&i| i == 2), 2);
+        assert_eq!(
+            [queue.dequeue(), queue.dequeue(), queue.dequeue()],
+            [3, 0, 1]
+        );
+    }
+
+    #[test]
+    fn dequeue_until_panic() {
+        let queue = SynchronizedQueue::new();
+        for i in 0..3 {
+            queue.enqueue(i);
+        }
+        let res = std::panic::catch_unwind(|| {
+            queue.dequeue_until_requeue(|&i| if i == 1 { panic!() } else { false })
+        });
+        assert!(res.is_err());
+        assert_eq!(
+            [queue.dequeue(), queue.dequeue(), queue.dequeue()],
+            [2, 0, 1]
+        );
+        assert_eq!(queue.try_dequeue(), Dequeue::Empty);
+    }
+
+    #[test]
+    fn dequeue_until_drop_panic() {
+        use std::sync::atomic::AtomicUsize;
+
+        struct Tracked(usize, Arc<AtomicUsize>);
+        impl Drop for Tracked {
+            fn drop(&mut self) {
+                self.1.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        let drops = Arc::new(AtomicUsize::new(0));
+        let queue = SynchronizedQueue::new();
+        (0..3).for_each(|i| queue.enqueue(Tracked(i, drops.clone())));
+        let res = std::panic::catch_unwind(|| {
+            queue.dequeue_until(|item| if item.0 == 1 { panic!() } else { false })
+        });
+        assert!(res.is_err());
+        // only the item tested before the panic is dropped, the panicking one is requeued
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+        assert_eq!(queue.dequeue_until(|item| item.0 == 1).0, 1);
+        assert_eq!(drops.load(Ordering::Relaxed), 3);
+        assert!(matches!(queue.try_dequeue(), Dequeue::Empty));
+        queue.enqueue(Tracked(3, drops.clone()));
+        assert_eq!(queue.dequeue().0, 3);
+        assert_eq!(drops.load(Ordering::Relaxed), 4);
+    }
+
     #[test]
     fn synchronized_async() {
         let queue = Arc::new(SynchronizedQueue::new());
@@ -190,4 +1433,291 @@ mod tests {
         }
         assert_eq!(futures::executor::block_on(queue.dequeue_async()), 0);
     }
+
+    #[test]
+    fn dequeue_detailed() {
+        use std::future::Future;
+        use std::pin::pin;
+        use std::sync::atomic::AtomicUsize;
+        use std::task::{Context, Poll};
+
+        struct CountWake(AtomicUsize);
+        impl futures::task::ArcWake for CountWake {
+            fn wake_by_ref(arc_self: &Arc<Self>) {
+                arc_self.0.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        let wakes = Arc::new(CountWake(AtomicUsize::new(0)));
+        let waker = futures::task::waker(wakes.clone());
+        let mut cx = Context::from_waker(&waker);
+        let queue = SynchronizedQueue::new();
+        let mut dequeue = pin!(queue.dequeue_detailed(0));
+        queue.enqueue(0);
+        assert_eq!(dequeue.as_mut().poll(&mut cx), Poll::Ready(Ok(0)));
+        assert_eq!(dequeue.as_mut().poll(&mut cx), Poll::Pending);
+        assert_eq!(wakes.0.load(Ordering::Relaxed), 0);
+        queue.enqueue(1);
+        assert_eq!(wakes.0.load(Ordering::Relaxed), 1);
+        assert_eq!(dequeue.as_mut().poll(&mut cx), Poll::Ready(Ok(1)));
+    }
+
+    #[test]
+    fn abandoned_detailed_dequeue() {
+        use std::future::Future;
+        use std::pin::pin;
+        use std::task::{Context, Poll};
+
+        let queue = Arc::new(SynchronizedQueue::new());
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        {
+            let mut dequeue = pin!(queue.dequeue_detailed(0));
+            assert_eq!(dequeue.as_mut().poll(&mut cx), Poll::Pending);
+            assert_eq!(dequeue.as_mut().poll(&mut cx), Poll::Pending);
+        }
+        assert_woken_after(&queue, 2);
+    }
+
+    #[test]
+    #[cfg(feature = "metrics")]
+    fn pending_async() {
+        use std::future::Future;
+        use std::pin::pin;
+        use std::task::{Context, Poll};
+
+        let queue = SynchronizedQueue::new();
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        let mut dequeue = pin!(queue.dequeue_async());
+        assert_eq!(dequeue.as_mut().poll(&mut cx), Poll::Pending);
+        assert_eq!(dequeue.as_mut().poll(&mut cx), Poll::Pending);
+        assert_eq!(queue.pending_async(), 1);
+        queue.enqueue(0);
+        assert_eq!(dequeue.as_mut().poll(&mut cx), Poll::Ready(0));
+        assert_eq!(queue.pending_async(), 0);
+        let mut dequeue = Box::pin(queue.dequeue_async());
+        assert_eq!(dequeue.as_mut().poll(&mut cx), Poll::Pending);
+        assert_eq!(queue.pending_async(), 1);
+        drop(dequeue);
+        assert_eq!(queue.pending_async(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "debug")]
+    fn detect_lost_wakeups() {
+        // a dead waker ahead of the consumer exhausts the scan limit of producers
+        let queue = Arc::new(SynchronizedQueue::with_wake_scan_limit(1));
+        let dead = Arc::new(Waker::new_sync());
+        dead.abort();
+        queue.wake_queue.enqueue(dead);
+        let consumer = {
+            let queue = queue.clone();
+            std::thread::spawn(move || queue.dequeue())
+        };
+        while queue.parked_sync.load(Ordering::Relaxed) == 0 {
+            std::thread::yield_now();
+        }
+        assert!(!queue.detect_lost_wakeups());
+        queue.enqueue(0);
+        assert!(queue.detect_lost_wakeups());
+        queue.rebind_waiters();
+        assert_eq!(consumer.join().unwrap(), 0);
+        assert!(!queue.detect_lost_wakeups());
+    }
+
+    #[test]
+    fn dequeue_async_bounded_polls() {
+        use std::future::Future;
+        use std::pin::pin;
+        use std::task::{Context, Poll};
+
+        let queue = SynchronizedQueue::new();
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        let mut dequeue = pin!(queue.dequeue_async_bounded_polls(3));
+        assert_eq!(dequeue.as_mut().poll(&mut cx), Poll::Pending);
+        assert_eq!(dequeue.as_mut().poll(&mut cx), Poll::Pending);
+        assert_eq!(dequeue.as_mut().poll(&mut cx), Poll::Ready(None));
+        let mut dequeue = pin!(queue.dequeue_async_bounded_polls(3));
+        assert_eq!(dequeue.as_mut().poll(&mut cx), Poll::Pending);
+        queue.enqueue(0);
+        assert_eq!(dequeue.as_mut().poll(&mut cx), Poll::Ready(Some(0)));
+    }
+
+    /// Spawn a consumer once `dead` wakers are registered, and check it is woken by an
+    /// enqueue, instead of timing out because the wakeup was spent on a dead waker.
+    fn assert_woken_after(queue: &Arc<SynchronizedQueue<i32>>, dead: usize) {
+        let consumer = {
+            let queue = queue.clone();
+            std::thread::spawn(move || {
+                let start = Instant::now();
+                let res = queue.dequeue_timeout(Duration::from_secs(10));
+                (res, start.elapsed())
+            })
+        };
+        while queue.wake_queue.approx_len() < dead + 1 {
+            std::thread::yield_now();
+        }
+        queue.enqueue(7);
+        let (res, elapsed) = consumer.join().unwrap();
+        assert_eq!(res, Dequeue::Data(7));
+        assert!(elapsed < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn cancelled_async_dequeue() {
+        use std::future::Future;
+        use std::pin::pin;
+        use std::task::{Context, Poll};
+
+        let queue = Arc::new(SynchronizedQueue::new());
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        let mut bounded = pin!(queue.dequeue_async_bounded_polls(2));
+        assert_eq!(bounded.as_mut().poll(&mut cx), Poll::Pending);
+        assert_eq!(bounded.as_mut().poll(&mut cx), Poll::Ready(None));
+        {
+            let mut dequeue = pin!(queue.dequeue_async());
+            assert_eq!(dequeue.as_mut().poll(&mut cx), Poll::Pending);
+        }
+        assert_woken_after(&queue, 3);
+    }
+
+    #[test]
+    fn timed_out_async_dequeue() {
+        use std::future::Future;
+        use std::pin::pin;
+        use std::task::{Context, Poll};
+
+        let queue = Arc::new(SynchronizedQueue::new());
+        let timeout = Duration::from_secs(1);
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        {
+            let pending = MockTimer { fired: false };
+            let mut dequeue = pin!(queue.dequeue_async_timeout_with(timeout, &pending));
+            assert_eq!(dequeue.as_mut().poll(&mut cx), Poll::Pending);
+        }
+        let fired = MockTimer { fired: true };
+        let mut dequeue = pin!(queue.dequeue_async_timeout_with(timeout, &fired));
+        assert_eq!(dequeue.as_mut().poll(&mut cx), Poll::Ready(Dequeue::Empty));
+        assert_woken_after(&queue, 2);
+    }
+
+    struct MockClock {
+        now: Cell<Instant>,
+        step: Duration,
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> Instant {
+            let now = self.now.get();
+            self.now.set(now + self.step);
+            now
+        }
+    }
+
+    #[test]
+    fn dequeue_timeout_with_clock() {
+        let queue = SynchronizedQueue::<usize>::new();
+        let clock = MockClock {
+            now: Cell::new(Instant::now()),
+            step: Duration::from_secs(10),
+        };
+        let timeout = Duration::from_secs(5);
+        assert_eq!(
+            queue.dequeue_timeout_with_clock(timeout, &clock),
+            Dequeue::Empty
+        );
+        queue.enqueue(0);
+        assert_eq!(
+            queue.dequeue_timeout_with_clock(timeout, &clock),
+            Dequeue::Data(0)
+        );
+    }
+
+    struct MockTimer {
+        fired: bool,
+    }
+
+    impl Timer for MockTimer {
+        async fn sleep(&self, _duration: Duration) {
+            if !self.fired {
+                std::future::pending::<()>().await
+            }
+        }
+    }
+
+    #[test]
+    fn dequeue_async_timeout_with() {
+        let queue = Arc::new(SynchronizedQueue::new());
+        let timeout = Duration::from_secs(1);
+        let fired = MockTimer { fired: true };
+        let dequeue = queue.dequeue_async_timeout_with(timeout, &fired);
+        assert_eq!(futures::executor::block_on(dequeue), Dequeue::Empty);
+        {
+            let queue = queue.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_micros(10));
+                queue.enqueue(0)
+            });
+        }
+        let pending = MockTimer { fired: false };
+        let dequeue = queue.dequeue_async_timeout_with(timeout, &pending);
+        assert_eq!(futures::executor::block_on(dequeue), Dequeue::Data(0));
+    }
+
+    struct TickTimer<'a> {
+        queue: &'a SynchronizedQueue<usize>,
+        ticks: Cell<usize>,
+    }
+
+    impl Timer for TickTimer<'_> {
+        async fn sleep(&self, _duration: Duration) {
+            self.ticks.set(self.ticks.get() + 1);
+            if self.ticks.get() == 3 {
+                self.queue.enqueue(42);
+            }
+        }
+    }
+
+    #[test]
+    fn windowed() {
+        let queue = SynchronizedQueue::new();
+        let timer = TickTimer {
+            queue: &queue,
+            ticks: Cell::new(0),
+        };
+        let mut windows = queue.windowed(Duration::from_secs(1), &timer);
+        queue.enqueue(0);
+        queue.enqueue(1);
+        assert_eq!(futures::executor::block_on(windows.next()), [0, 1]);
+        assert_eq!(futures::executor::block_on(windows.next()), []);
+        let mut windows = windows.skip_empty();
+        assert_eq!(futures::executor::block_on(windows.next()), [42]);
+        assert_eq!(timer.ticks.get(), 3);
+    }
+
+    #[test]
+    fn windowed_yield_budget() {
+        use std::future::Future;
+        use std::pin::pin;
+        use std::task::{Context, Poll};
+
+        let queue = SynchronizedQueue::new();
+        let timer = TickTimer {
+            queue: &queue,
+            ticks: Cell::new(0),
+        };
+        let mut windows = queue
+            .windowed(Duration::from_secs(1), &timer)
+            .yield_budget(100);
+        (0..1000).for_each(|i| queue.enqueue(i));
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        let mut next = pin!(windows.next());
+        let mut polls = 1;
+        let items = loop {
+            match next.as_mut().poll(&mut cx) {
+                Poll::Ready(items) => break items,
+                Poll::Pending => polls += 1,
+            }
+        };
+        assert_eq!(polls, 11);
+        assert_eq!(items, (0..1000).collect::<Vec<_>>());
+    }
 }